@@ -0,0 +1,1358 @@
+#![allow(dead_code)]
+
+use crate::state::{StateReader, StateWriter};
+use std::collections::VecDeque;
+
+// Length counter values indexed by the top five bits of a channel's last register
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
+    192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+// 12.5%, 25%, 50% and 75% (negated 25%) square waves
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+// The triangle steps down from 15 to 0 and back up
+const TRIANGLE_SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12,
+    13, 14, 15,
+];
+
+// Timer periods in CPU cycles, indexed by the low four bits of 0x400E and 0x4010 (NTSC)
+const NOISE_PERIOD_TABLE: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+const DMC_RATE_TABLE: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+
+// Status / channel enable (0x4015)
+const STATUS_PULSE_1: u8 = 0b0000_0001;
+const STATUS_PULSE_2: u8 = 0b0000_0010;
+const STATUS_TRIANGLE: u8 = 0b0000_0100;
+const STATUS_NOISE: u8 = 0b0000_1000;
+const STATUS_DMC: u8 = 0b0001_0000;
+const STATUS_FRAME_IRQ: u8 = 0b0100_0000;
+const STATUS_DMC_IRQ: u8 = 0b1000_0000;
+
+// Frame counter (0x4017)
+const FRAME_FIVE_STEP: u8 = 0b1000_0000;
+const FRAME_IRQ_INHIBIT: u8 = 0b0100_0000;
+
+// Frame sequencer steps, in CPU cycles since the sequence started
+const FRAME_STEP_1: usize = 7457;
+const FRAME_STEP_2: usize = 14913;
+const FRAME_STEP_3: usize = 22371;
+const FOUR_STEP_LAST: usize = 29829;
+const FIVE_STEP_LAST: usize = 37281;
+
+// Samples are read from 0xC000 upwards and wrap around to 0x8000
+const DMC_SAMPLE_BASE: u16 = 0xC000;
+// Cycles the CPU is held for each sample byte fetched
+pub const DMC_DMA_CYCLES: usize = 4;
+
+// Channels that produce a frequency above this are silenced by the sweep unit
+const MAX_PULSE_PERIOD: u16 = 0x7FF;
+
+// The mixer is sampled once per CPU cycle (NTSC) and resampled when drained
+const CPU_CLOCK_HZ: f64 = 1_789_773.0;
+const DEFAULT_OUTPUT_RATE: u32 = 44_100;
+// About 0.15s of input; the oldest samples are dropped if the host stops draining
+const AUDIO_BUFFER_CAPACITY: usize = 1 << 18;
+
+// Volume source for the pulse and noise channels. Clocked every quarter frame.
+#[derive(Default)]
+struct Envelope {
+    start: bool,
+    looping: bool,
+    constant: bool,
+    // Constant volume, or the divider period when decaying
+    volume: u8,
+    divider: u8,
+    decay: u8,
+}
+
+impl Envelope {
+    fn write(&mut self, data: u8) {
+        self.looping = data & 0b0010_0000 != 0;
+        self.constant = data & 0b0001_0000 != 0;
+        self.volume = data & 0b0000_1111;
+    }
+
+    fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.looping {
+                self.decay = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.constant {
+            self.volume
+        } else {
+            self.decay
+        }
+    }
+}
+
+// Silences a channel after a set number of half frames unless halted
+#[derive(Default)]
+struct LengthCounter {
+    enabled: bool,
+    halted: bool,
+    counter: u8,
+}
+
+impl LengthCounter {
+    // Loads are ignored while the channel is disabled in 0x4015
+    fn load(&mut self, index: u8) {
+        if self.enabled {
+            self.counter = LENGTH_TABLE[index as usize];
+        }
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.counter = 0;
+        }
+    }
+
+    fn clock(&mut self) {
+        if !self.halted && self.counter > 0 {
+            self.counter -= 1;
+        }
+    }
+
+    fn active(&self) -> bool {
+        self.counter > 0
+    }
+}
+
+// Periodically bends the pulse period up or down. Clocked every half frame.
+#[derive(Default)]
+struct Sweep {
+    enabled: bool,
+    period: u8,
+    negate: bool,
+    shift: u8,
+    reload: bool,
+    divider: u8,
+}
+
+#[derive(Default)]
+struct Pulse {
+    // Pulse 1 negates with one's complement, pulse 2 with two's complement
+    ones_complement: bool,
+    duty: u8,
+    step: u8,
+    timer_period: u16,
+    timer: u16,
+    envelope: Envelope,
+    length: LengthCounter,
+    sweep: Sweep,
+}
+
+impl Pulse {
+    fn new(ones_complement: bool) -> Self {
+        Pulse {
+            ones_complement,
+            ..Default::default()
+        }
+    }
+
+    // `register` is 0-3 within the channel's four-byte window
+    fn write_register(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.duty = data >> 6;
+                self.length.halted = data & 0b0010_0000 != 0;
+                self.envelope.write(data);
+            }
+            1 => {
+                self.sweep.enabled = data & 0b1000_0000 != 0;
+                self.sweep.period = (data >> 4) & 0b111;
+                self.sweep.negate = data & 0b0000_1000 != 0;
+                self.sweep.shift = data & 0b111;
+                self.sweep.reload = true;
+            }
+            2 => self.timer_period = (self.timer_period & 0x0700) | data as u16,
+            _ => {
+                self.timer_period = (self.timer_period & 0x00FF) | ((data as u16 & 0b111) << 8);
+                self.length.load(data >> 3);
+                self.envelope.start = true;
+                self.step = 0;
+            }
+        }
+    }
+
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            self.step = (self.step + 1) % 8;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn sweep_target(&self) -> u16 {
+        let change = self.timer_period >> self.sweep.shift;
+        if !self.sweep.negate {
+            self.timer_period + change
+        } else if self.ones_complement {
+            self.timer_period.saturating_sub(change + 1)
+        } else {
+            self.timer_period.saturating_sub(change)
+        }
+    }
+
+    fn clock_sweep(&mut self) {
+        if self.sweep.divider == 0 && self.sweep.enabled && self.sweep.shift > 0 && !self.muted() {
+            self.timer_period = self.sweep_target();
+        }
+
+        if self.sweep.divider == 0 || self.sweep.reload {
+            self.sweep.divider = self.sweep.period;
+            self.sweep.reload = false;
+        } else {
+            self.sweep.divider -= 1;
+        }
+    }
+
+    fn muted(&self) -> bool {
+        self.timer_period < 8 || self.sweep_target() > MAX_PULSE_PERIOD
+    }
+
+    fn output(&self) -> u8 {
+        if !self.length.active()
+            || self.muted()
+            || DUTY_TABLE[self.duty as usize][self.step as usize] == 0
+        {
+            return 0;
+        }
+        self.envelope.output()
+    }
+}
+
+#[derive(Default)]
+struct Triangle {
+    step: u8,
+    timer_period: u16,
+    timer: u16,
+    length: LengthCounter,
+    // Also halts the length counter
+    control: bool,
+    linear_reload_value: u8,
+    linear_counter: u8,
+    linear_reload: bool,
+}
+
+impl Triangle {
+    fn write_register(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.control = data & 0b1000_0000 != 0;
+                self.length.halted = self.control;
+                self.linear_reload_value = data & 0b0111_1111;
+            }
+            1 => {} // Unused
+            2 => self.timer_period = (self.timer_period & 0x0700) | data as u16,
+            _ => {
+                self.timer_period = (self.timer_period & 0x00FF) | ((data as u16 & 0b111) << 8);
+                self.length.load(data >> 3);
+                self.linear_reload = true;
+            }
+        }
+    }
+
+    // The sequencer only advances while both counters are non-zero
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            if self.length.active() && self.linear_counter > 0 {
+                self.step = (self.step + 1) % 32;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_linear_counter(&mut self) {
+        if self.linear_reload {
+            self.linear_counter = self.linear_reload_value;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    // A silenced triangle holds its last level instead of dropping to 0, avoiding a click
+    fn output(&self) -> u8 {
+        TRIANGLE_SEQUENCE[self.step as usize]
+    }
+}
+
+struct Noise {
+    // Mode 1 taps bit 6 instead of bit 1, giving a short metallic loop
+    short_mode: bool,
+    shift_register: u16,
+    timer_period: u16,
+    timer: u16,
+    envelope: Envelope,
+    length: LengthCounter,
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        Noise {
+            short_mode: false,
+            shift_register: 1,
+            timer_period: NOISE_PERIOD_TABLE[0],
+            timer: 0,
+            envelope: Envelope::default(),
+            length: LengthCounter::default(),
+        }
+    }
+}
+
+impl Noise {
+    fn write_register(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.length.halted = data & 0b0010_0000 != 0;
+                self.envelope.write(data);
+            }
+            1 => {} // Unused
+            2 => {
+                self.short_mode = data & 0b1000_0000 != 0;
+                self.timer_period = NOISE_PERIOD_TABLE[(data & 0b1111) as usize];
+            }
+            _ => {
+                self.length.load(data >> 3);
+                self.envelope.start = true;
+            }
+        }
+    }
+
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period - 1;
+            self.clock_shift_register();
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    // 15-bit LFSR: shift right, feeding bit 0 XOR the tap back into bit 14
+    fn clock_shift_register(&mut self) {
+        let tap = if self.short_mode { 6 } else { 1 };
+        let feedback = (self.shift_register ^ (self.shift_register >> tap)) & 1;
+        self.shift_register = (self.shift_register >> 1) | (feedback << 14);
+    }
+
+    fn output(&self) -> u8 {
+        if !self.length.active() || self.shift_register & 1 == 1 {
+            return 0;
+        }
+        self.envelope.output()
+    }
+}
+
+// Delta modulation channel: plays 1-bit delta samples fetched from CPU memory
+struct Dmc {
+    irq_enabled: bool,
+    irq: bool,
+    looping: bool,
+    timer_period: u16,
+    timer: u16,
+    output_level: u8,
+
+    sample_address: u16,
+    sample_length: u16,
+    current_address: u16,
+    bytes_remaining: u16,
+    buffer: Option<u8>,
+
+    shift_register: u8,
+    bits_remaining: u8,
+    silence: bool,
+}
+
+impl Default for Dmc {
+    fn default() -> Self {
+        Dmc {
+            irq_enabled: false,
+            irq: false,
+            looping: false,
+            timer_period: DMC_RATE_TABLE[0],
+            timer: 0,
+            output_level: 0,
+            sample_address: DMC_SAMPLE_BASE,
+            sample_length: 1,
+            current_address: DMC_SAMPLE_BASE,
+            bytes_remaining: 0,
+            buffer: None,
+            shift_register: 0,
+            bits_remaining: 0,
+            silence: true,
+        }
+    }
+}
+
+impl Dmc {
+    fn write_register(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.irq_enabled = data & 0b1000_0000 != 0;
+                self.looping = data & 0b0100_0000 != 0;
+                self.timer_period = DMC_RATE_TABLE[(data & 0b1111) as usize];
+                if !self.irq_enabled {
+                    self.irq = false;
+                }
+            }
+            1 => self.output_level = data & 0b0111_1111,
+            2 => self.sample_address = DMC_SAMPLE_BASE + data as u16 * 64,
+            _ => self.sample_length = data as u16 * 16 + 1,
+        }
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    // Address of the next sample byte, once the buffer has room for it
+    fn dma_request(&self) -> Option<u16> {
+        if self.buffer.is_none() && self.bytes_remaining > 0 {
+            Some(self.current_address)
+        } else {
+            None
+        }
+    }
+
+    fn dma_fill(&mut self, data: u8) {
+        self.buffer = Some(data);
+        self.current_address = self.current_address.checked_add(1).unwrap_or(0x8000);
+        self.bytes_remaining -= 1;
+
+        if self.bytes_remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq = true;
+            }
+        }
+    }
+
+    fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.timer_period.saturating_sub(1);
+
+        // Each bit nudges the level up or down by 2, clamped to 0..=127
+        if !self.silence {
+            if self.shift_register & 1 == 1 {
+                if self.output_level <= 125 {
+                    self.output_level += 2;
+                }
+            } else if self.output_level >= 2 {
+                self.output_level -= 2;
+            }
+        }
+        self.shift_register >>= 1;
+
+        if self.bits_remaining > 0 {
+            self.bits_remaining -= 1;
+        }
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.buffer.take() {
+                Some(byte) => {
+                    self.silence = false;
+                    self.shift_register = byte;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+}
+
+// The 2A03's audio unit, mapped at 0x4000-0x4017
+pub struct Apu {
+    pulse_1: Pulse,
+    pulse_2: Pulse,
+    triangle: Triangle,
+    noise: Noise,
+    dmc: Dmc,
+    cycle: u64,
+
+    five_step_mode: bool,
+    frame_irq_inhibit: bool,
+    frame_irq: bool,
+    frame_cycle: usize,
+
+    // Host-side output, not part of save states
+    audio_enabled: bool,
+    audio_buffer: VecDeque<f32>,
+    output_rate: u32,
+    // Offset of the next output sample into audio_buffer, in input samples
+    resample_position: f64,
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        Apu {
+            pulse_1: Pulse::new(true),
+            pulse_2: Pulse::new(false),
+            triangle: Triangle::default(),
+            noise: Noise::default(),
+            dmc: Dmc::default(),
+            cycle: 0,
+            five_step_mode: false,
+            frame_irq_inhibit: false,
+            frame_irq: false,
+            frame_cycle: 0,
+            audio_enabled: true,
+            audio_buffer: VecDeque::with_capacity(AUDIO_BUFFER_CAPACITY),
+            output_rate: DEFAULT_OUTPUT_RATE,
+            resample_position: 0.0,
+        }
+    }
+
+    pub fn write_register(&mut self, address: u16, data: u8) {
+        match address {
+            0x4000..=0x4003 => self.pulse_1.write_register(address & 0b11, data),
+            0x4004..=0x4007 => self.pulse_2.write_register(address & 0b11, data),
+            0x4008..=0x400B => self.triangle.write_register(address & 0b11, data),
+            0x400C..=0x400F => self.noise.write_register(address & 0b11, data),
+            0x4010..=0x4013 => self.dmc.write_register(address & 0b11, data),
+            0x4015 => {
+                self.pulse_1.length.set_enabled(data & STATUS_PULSE_1 != 0);
+                self.pulse_2.length.set_enabled(data & STATUS_PULSE_2 != 0);
+                self.triangle
+                    .length
+                    .set_enabled(data & STATUS_TRIANGLE != 0);
+                self.noise.length.set_enabled(data & STATUS_NOISE != 0);
+                self.dmc.set_enabled(data & STATUS_DMC != 0);
+                self.dmc.irq = false;
+            }
+            0x4017 => self.write_frame_counter(data),
+            _ => {}
+        }
+    }
+
+    // 0x4015: which channels still have length remaining, and pending IRQs
+    // Reading acknowledges the frame IRQ
+    pub fn read_status(&mut self) -> u8 {
+        let status = self.peek_status();
+        self.frame_irq = false;
+        status
+    }
+
+    pub fn peek_status(&self) -> u8 {
+        let mut status = 0;
+        if self.pulse_1.length.active() {
+            status |= STATUS_PULSE_1;
+        }
+        if self.pulse_2.length.active() {
+            status |= STATUS_PULSE_2;
+        }
+        if self.triangle.length.active() {
+            status |= STATUS_TRIANGLE;
+        }
+        if self.noise.length.active() {
+            status |= STATUS_NOISE;
+        }
+        if self.dmc.bytes_remaining > 0 {
+            status |= STATUS_DMC;
+        }
+        if self.frame_irq {
+            status |= STATUS_FRAME_IRQ;
+        }
+        if self.dmc.irq {
+            status |= STATUS_DMC_IRQ;
+        }
+        status
+    }
+
+    // Advances the channel timers by `cycles` CPU cycles. Pulse timers run at half rate.
+    pub fn tick(&mut self, cycles: usize) {
+        for _ in 0..cycles {
+            self.cycle += 1;
+            self.clock_frame_counter();
+            self.triangle.clock_timer();
+            self.noise.clock_timer();
+            self.dmc.clock_timer();
+            if self.cycle.is_multiple_of(2) {
+                self.pulse_1.clock_timer();
+                self.pulse_2.clock_timer();
+            }
+
+            if !self.audio_enabled {
+                continue;
+            }
+            if self.audio_buffer.len() == AUDIO_BUFFER_CAPACITY {
+                self.audio_buffer.pop_front();
+            }
+            let sample = self.sample();
+            self.audio_buffer.push_back(sample);
+        }
+    }
+
+    // Disabling skips mixing and buffering; the channels keep running either way
+    pub fn set_audio_enabled(&mut self, enabled: bool) {
+        self.audio_enabled = enabled;
+    }
+
+    pub fn audio_enabled(&self) -> bool {
+        self.audio_enabled
+    }
+
+    pub fn set_output_rate(&mut self, rate: u32) {
+        assert!(rate > 0, "output rate must be positive");
+        self.output_rate = rate;
+    }
+
+    // Fills `out` with samples at the output rate and returns how many were written.
+    // Each output sample averages the input samples it covers; the fractional
+    // position carries over between calls so the rate does not drift.
+    pub fn drain_audio(&mut self, out: &mut [f32]) -> usize {
+        let step = CPU_CLOCK_HZ / self.output_rate as f64;
+        let mut written = 0;
+
+        while written < out.len() {
+            let end = self.resample_position + step;
+            if end > self.audio_buffer.len() as f64 {
+                break;
+            }
+
+            let first = self.resample_position as usize;
+            let last = (end as usize).max(first + 1);
+            let sum: f32 = self.audio_buffer.range(first..last).sum();
+            out[written] = sum / (last - first) as f32;
+
+            self.resample_position = end;
+            written += 1;
+        }
+
+        let consumed = self.resample_position as usize;
+        self.audio_buffer.drain(..consumed);
+        self.resample_position -= consumed as f64;
+        written
+    }
+
+    // The bus services these: it reads the byte at the returned address and
+    // passes it to dmc_dma_fill, stalling the CPU for DMC_DMA_CYCLES
+    pub fn dmc_dma_request(&self) -> Option<u16> {
+        self.dmc.dma_request()
+    }
+
+    pub fn dmc_dma_fill(&mut self, data: u8) {
+        self.dmc.dma_fill(data);
+    }
+
+    // True while a sample is playing and may still request bytes
+    pub fn dmc_active(&self) -> bool {
+        self.dmc.bytes_remaining > 0
+    }
+
+    // The APU's IRQ line, held until acknowledged through 0x4015
+    pub fn irq(&self) -> bool {
+        self.frame_irq || self.dmc.irq
+    }
+
+    // Selecting 5-step mode clocks the units straight away
+    fn write_frame_counter(&mut self, data: u8) {
+        self.five_step_mode = data & FRAME_FIVE_STEP != 0;
+        self.frame_irq_inhibit = data & FRAME_IRQ_INHIBIT != 0;
+        if self.frame_irq_inhibit {
+            self.frame_irq = false;
+        }
+
+        self.frame_cycle = 0;
+        if self.five_step_mode {
+            self.quarter_frame();
+            self.half_frame();
+        }
+    }
+
+    // 4-step: quarter, half, quarter, half + IRQ. 5-step: quarter, half, quarter, -, half.
+    fn clock_frame_counter(&mut self) {
+        self.frame_cycle += 1;
+        match self.frame_cycle {
+            FRAME_STEP_1 | FRAME_STEP_3 => self.quarter_frame(),
+            FRAME_STEP_2 => {
+                self.quarter_frame();
+                self.half_frame();
+            }
+            FOUR_STEP_LAST if !self.five_step_mode => {
+                self.quarter_frame();
+                self.half_frame();
+                if !self.frame_irq_inhibit {
+                    self.frame_irq = true;
+                }
+                self.frame_cycle = 0;
+            }
+            FIVE_STEP_LAST => {
+                self.quarter_frame();
+                self.half_frame();
+                self.frame_cycle = 0;
+            }
+            _ => {}
+        }
+    }
+
+    // Envelopes and the triangle's linear counter
+    pub fn quarter_frame(&mut self) {
+        self.pulse_1.envelope.clock();
+        self.pulse_2.envelope.clock();
+        self.noise.envelope.clock();
+        self.triangle.clock_linear_counter();
+    }
+
+    // Length counters and sweeps
+    pub fn half_frame(&mut self) {
+        self.pulse_1.length.clock();
+        self.pulse_2.length.clock();
+        self.triangle.length.clock();
+        self.noise.length.clock();
+        self.pulse_1.clock_sweep();
+        self.pulse_2.clock_sweep();
+    }
+
+    // Current mixer output in 0.0..=1.0, using the standard non-linear mix
+    pub fn sample(&mut self) -> f32 {
+        let pulse = (self.pulse_1.output() + self.pulse_2.output()) as f32;
+        let pulse_out = if pulse == 0.0 {
+            0.0
+        } else {
+            95.88 / (8128.0 / pulse + 100.0)
+        };
+
+        let tnd = self.triangle.output() as f32 / 8227.0
+            + self.noise.output() as f32 / 12241.0
+            + self.dmc.output_level as f32 / 22638.0;
+        let tnd_out = if tnd == 0.0 {
+            0.0
+        } else {
+            159.79 / (1.0 / tnd + 100.0)
+        };
+
+        pulse_out + tnd_out
+    }
+}
+
+// Save states: every unit writes and reads its fields in declaration order
+
+impl Envelope {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.bool(self.start);
+        state.bool(self.looping);
+        state.bool(self.constant);
+        state.u8(self.volume);
+        state.u8(self.divider);
+        state.u8(self.decay);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.start = state.bool()?;
+        self.looping = state.bool()?;
+        self.constant = state.bool()?;
+        self.volume = state.u8()?;
+        self.divider = state.u8()?;
+        self.decay = state.u8()?;
+        Ok(())
+    }
+}
+
+impl LengthCounter {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.bool(self.enabled);
+        state.bool(self.halted);
+        state.u8(self.counter);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.enabled = state.bool()?;
+        self.halted = state.bool()?;
+        self.counter = state.u8()?;
+        Ok(())
+    }
+}
+
+impl Sweep {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.bool(self.enabled);
+        state.u8(self.period);
+        state.bool(self.negate);
+        state.u8(self.shift);
+        state.bool(self.reload);
+        state.u8(self.divider);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.enabled = state.bool()?;
+        self.period = state.u8()?;
+        self.negate = state.bool()?;
+        self.shift = state.u8()?;
+        self.reload = state.bool()?;
+        self.divider = state.u8()?;
+        Ok(())
+    }
+}
+
+impl Pulse {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.u8(self.duty);
+        state.u8(self.step);
+        state.u16(self.timer_period);
+        state.u16(self.timer);
+        self.envelope.save_state(state);
+        self.length.save_state(state);
+        self.sweep.save_state(state);
+    }
+
+    // ones_complement is wiring, not state
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.duty = state.u8()?;
+        self.step = state.u8()?;
+        self.timer_period = state.u16()?;
+        self.timer = state.u16()?;
+        self.envelope.load_state(state)?;
+        self.length.load_state(state)?;
+        self.sweep.load_state(state)
+    }
+}
+
+impl Triangle {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.u8(self.step);
+        state.u16(self.timer_period);
+        state.u16(self.timer);
+        self.length.save_state(state);
+        state.bool(self.control);
+        state.u8(self.linear_reload_value);
+        state.u8(self.linear_counter);
+        state.bool(self.linear_reload);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.step = state.u8()?;
+        self.timer_period = state.u16()?;
+        self.timer = state.u16()?;
+        self.length.load_state(state)?;
+        self.control = state.bool()?;
+        self.linear_reload_value = state.u8()?;
+        self.linear_counter = state.u8()?;
+        self.linear_reload = state.bool()?;
+        Ok(())
+    }
+}
+
+impl Noise {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.bool(self.short_mode);
+        state.u16(self.shift_register);
+        state.u16(self.timer_period);
+        state.u16(self.timer);
+        self.envelope.save_state(state);
+        self.length.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.short_mode = state.bool()?;
+        self.shift_register = state.u16()?;
+        self.timer_period = state.u16()?;
+        self.timer = state.u16()?;
+        self.envelope.load_state(state)?;
+        self.length.load_state(state)
+    }
+}
+
+impl Dmc {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.bool(self.irq_enabled);
+        state.bool(self.irq);
+        state.bool(self.looping);
+        state.u16(self.timer_period);
+        state.u16(self.timer);
+        state.u8(self.output_level);
+        state.u16(self.sample_address);
+        state.u16(self.sample_length);
+        state.u16(self.current_address);
+        state.u16(self.bytes_remaining);
+        state.bool(self.buffer.is_some());
+        state.u8(self.buffer.unwrap_or(0));
+        state.u8(self.shift_register);
+        state.u8(self.bits_remaining);
+        state.bool(self.silence);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.irq_enabled = state.bool()?;
+        self.irq = state.bool()?;
+        self.looping = state.bool()?;
+        self.timer_period = state.u16()?;
+        self.timer = state.u16()?;
+        self.output_level = state.u8()?;
+        self.sample_address = state.u16()?;
+        self.sample_length = state.u16()?;
+        self.current_address = state.u16()?;
+        self.bytes_remaining = state.u16()?;
+        let has_buffer = state.bool()?;
+        let buffer = state.u8()?;
+        self.buffer = has_buffer.then_some(buffer);
+        self.shift_register = state.u8()?;
+        self.bits_remaining = state.u8()?;
+        self.silence = state.bool()?;
+        Ok(())
+    }
+}
+
+impl Apu {
+    pub fn save_state(&self, state: &mut StateWriter) {
+        self.pulse_1.save_state(state);
+        self.pulse_2.save_state(state);
+        self.triangle.save_state(state);
+        self.noise.save_state(state);
+        self.dmc.save_state(state);
+        state.u64(self.cycle);
+        state.bool(self.five_step_mode);
+        state.bool(self.frame_irq_inhibit);
+        state.bool(self.frame_irq);
+        state.usize(self.frame_cycle);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.pulse_1.load_state(state)?;
+        self.pulse_2.load_state(state)?;
+        self.triangle.load_state(state)?;
+        self.noise.load_state(state)?;
+        self.dmc.load_state(state)?;
+        self.cycle = state.u64()?;
+        self.five_step_mode = state.bool()?;
+        self.frame_irq_inhibit = state.bool()?;
+        self.frame_irq = state.bool()?;
+        self.frame_cycle = state.usize()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Pulse 1 at a 50% duty, audible period, loaded with length table entry `index`
+    fn start_pulse_1(apu: &mut Apu, control: u8, index: u8) {
+        apu.write_register(0x4015, STATUS_PULSE_1);
+        apu.write_register(0x4000, 0b1000_0000 | control);
+        apu.write_register(0x4002, 0xFF);
+        apu.write_register(0x4003, index << 3);
+    }
+
+    #[test]
+    fn length_counter_silences_channel() {
+        let mut apu = Apu::new();
+        start_pulse_1(&mut apu, 0b0001_1111, 3); // constant volume 15, length 2
+        assert_eq!(apu.read_status(), STATUS_PULSE_1);
+
+        apu.half_frame();
+        assert_eq!(apu.read_status(), STATUS_PULSE_1);
+        apu.half_frame();
+        assert_eq!(apu.read_status(), 0);
+        assert_eq!(apu.pulse_1.output(), 0);
+    }
+
+    #[test]
+    fn length_counter_halt_keeps_channel_playing() {
+        let mut apu = Apu::new();
+        start_pulse_1(&mut apu, 0b0011_1111, 3); // halt set
+        for _ in 0..10 {
+            apu.half_frame();
+        }
+        assert_eq!(apu.read_status(), STATUS_PULSE_1);
+
+        // Disabling the channel clears its length immediately
+        apu.write_register(0x4015, 0);
+        assert_eq!(apu.read_status(), 0);
+    }
+
+    #[test]
+    fn length_is_not_loaded_while_disabled() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4003, 1 << 3);
+        assert_eq!(apu.read_status(), 0);
+    }
+
+    #[test]
+    fn envelope_decays_on_quarter_frames() {
+        let mut apu = Apu::new();
+        start_pulse_1(&mut apu, 0b0000_0010, 1); // decaying, divider period 2
+
+        apu.quarter_frame();
+        assert_eq!(apu.pulse_1.envelope.output(), 15);
+
+        // Each step takes period + 1 clocks
+        for _ in 0..3 {
+            apu.quarter_frame();
+        }
+        assert_eq!(apu.pulse_1.envelope.output(), 14);
+
+        for _ in 0..3 * 20 {
+            apu.quarter_frame();
+        }
+        assert_eq!(apu.pulse_1.envelope.output(), 0);
+    }
+
+    #[test]
+    fn looping_envelope_restarts_at_15() {
+        let mut apu = Apu::new();
+        start_pulse_1(&mut apu, 0b0010_0000, 1); // loop, divider period 0
+
+        for _ in 0..16 {
+            apu.quarter_frame();
+        }
+        assert_eq!(apu.pulse_1.envelope.output(), 0);
+        apu.quarter_frame();
+        assert_eq!(apu.pulse_1.envelope.output(), 15);
+    }
+
+    #[test]
+    fn sweep_moves_the_period() {
+        let mut apu = Apu::new();
+        start_pulse_1(&mut apu, 0b0001_1111, 1);
+        apu.write_register(0x4001, 0b1000_0001); // enabled, period 0, shift 1
+        apu.write_register(0x4002, 0x00);
+        apu.write_register(0x4003, 0b0000_1001); // period 0x100
+
+        apu.half_frame();
+        assert_eq!(apu.pulse_1.timer_period, 0x180);
+    }
+
+    #[test]
+    fn sample_mixes_pulse_output() {
+        let mut apu = Apu::new();
+        // The idle triangle rests at the top of its sequence
+        apu.triangle.step = 15;
+        assert_eq!(apu.sample(), 0.0);
+
+        start_pulse_1(&mut apu, 0b0001_1111, 1);
+        // Step the duty sequencer onto a high step
+        apu.pulse_1.step = 1;
+
+        let expected = 95.88 / (8128.0 / 15.0 + 100.0);
+        assert!((apu.sample() - expected).abs() < 1e-6);
+    }
+
+    // Triangle with control flag `control`, linear reload `linear`, period 0
+    fn start_triangle(apu: &mut Apu, control: bool, linear: u8) {
+        apu.write_register(0x4015, STATUS_TRIANGLE);
+        apu.write_register(0x4008, (control as u8) << 7 | linear);
+        apu.write_register(0x400A, 0x00);
+        apu.write_register(0x400B, 1 << 3);
+    }
+
+    #[test]
+    fn triangle_linear_counter_reloads_once_without_control() {
+        let mut apu = Apu::new();
+        start_triangle(&mut apu, false, 3);
+
+        apu.quarter_frame();
+        assert_eq!(apu.triangle.linear_counter, 3);
+        assert!(!apu.triangle.linear_reload);
+
+        apu.quarter_frame();
+        apu.quarter_frame();
+        assert_eq!(apu.triangle.linear_counter, 1);
+        apu.quarter_frame();
+        apu.quarter_frame();
+        assert_eq!(apu.triangle.linear_counter, 0);
+    }
+
+    #[test]
+    fn triangle_control_flag_keeps_reloading() {
+        let mut apu = Apu::new();
+        start_triangle(&mut apu, true, 3);
+
+        for _ in 0..10 {
+            apu.quarter_frame();
+            assert_eq!(apu.triangle.linear_counter, 3);
+        }
+        assert!(apu.triangle.linear_reload);
+    }
+
+    #[test]
+    fn triangle_sequencer_steps_through_the_wave() {
+        let mut apu = Apu::new();
+        start_triangle(&mut apu, true, 10);
+        apu.quarter_frame();
+
+        // With a period of 0 the sequencer advances every CPU cycle
+        let levels: Vec<u8> = (0..32)
+            .map(|_| {
+                apu.tick(1);
+                apu.triangle.output()
+            })
+            .collect();
+        let mut expected: Vec<u8> = TRIANGLE_SEQUENCE[1..].to_vec();
+        expected.push(TRIANGLE_SEQUENCE[0]);
+        assert_eq!(levels, expected);
+    }
+
+    #[test]
+    fn silenced_triangle_freezes_its_output() {
+        let mut apu = Apu::new();
+        start_triangle(&mut apu, false, 1);
+        apu.quarter_frame();
+        apu.tick(5);
+        let level = apu.triangle.output();
+
+        // Linear counter runs out
+        apu.quarter_frame();
+        apu.tick(5);
+        assert_eq!(apu.triangle.output(), level);
+        assert!(apu.sample() > 0.0);
+    }
+
+    // Clocks the LFSR until it returns to its seed
+    fn lfsr_period(short_mode: bool) -> usize {
+        let mut noise = Noise {
+            short_mode,
+            ..Default::default()
+        };
+        let seed = noise.shift_register;
+        let mut period = 0;
+        loop {
+            noise.clock_shift_register();
+            period += 1;
+            if noise.shift_register == seed {
+                return period;
+            }
+        }
+    }
+
+    #[test]
+    fn noise_lfsr_periods() {
+        assert_eq!(lfsr_period(false), 32767);
+        assert_eq!(lfsr_period(true), 93);
+    }
+
+    #[test]
+    fn noise_is_silent_when_bit_0_is_set() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4015, STATUS_NOISE);
+        apu.write_register(0x400C, 0b0001_1111);
+        apu.write_register(0x400F, 1 << 3);
+
+        // The seed has bit 0 set
+        assert_eq!(apu.noise.output(), 0);
+        apu.noise.clock_shift_register();
+        assert_eq!(apu.noise.shift_register & 1, 0);
+        assert_eq!(apu.noise.output(), 15);
+    }
+
+    // Feeds the DMC the way the bus would, returning how many bytes were fetched
+    fn run_dmc(apu: &mut Apu, cycles: usize, sample: &[u8]) -> usize {
+        let mut fetched = 0;
+        for _ in 0..cycles {
+            apu.tick(1);
+            if let Some(addr) = apu.dmc_dma_request() {
+                let offset = (addr - DMC_SAMPLE_BASE) as usize % sample.len();
+                apu.dmc_dma_fill(sample[offset]);
+                fetched += 1;
+            }
+        }
+        fetched
+    }
+
+    #[test]
+    fn dmc_irq_fires_at_end_of_sample() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4010, 0b1000_1111); // IRQ enabled, fastest rate
+        apu.write_register(0x4012, 0x00); // $C000
+        apu.write_register(0x4013, 0x01); // 17 bytes
+        apu.write_register(0x4015, STATUS_DMC);
+        assert_eq!(apu.read_status() & STATUS_DMC, STATUS_DMC);
+
+        // A byte lasts 8 output clocks of 54 cycles
+        let fetched = run_dmc(&mut apu, 54 * 8 * 8, &[0xFF; 17]);
+        assert!(fetched < 17);
+        assert!(!apu.irq());
+
+        let fetched = fetched + run_dmc(&mut apu, 54 * 8 * 16, &[0xFF; 17]);
+        assert_eq!(fetched, 17);
+        assert!(apu.irq());
+        assert_eq!(
+            apu.read_status() & (STATUS_DMC | STATUS_DMC_IRQ),
+            STATUS_DMC_IRQ
+        );
+
+        // Writing 0x4015 acknowledges
+        apu.write_register(0x4015, 0);
+        assert!(!apu.irq());
+    }
+
+    #[test]
+    fn dmc_without_irq_enabled_stays_quiet() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4010, 0b0000_1111);
+        apu.write_register(0x4013, 0x00); // 1 byte
+        apu.write_register(0x4015, STATUS_DMC);
+
+        run_dmc(&mut apu, 1000, &[0xFF]);
+        assert!(!apu.irq());
+        assert_eq!(apu.read_status() & STATUS_DMC, 0);
+    }
+
+    #[test]
+    fn dmc_bits_move_the_output_level() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4010, 0b0000_1111);
+        apu.write_register(0x4011, 64);
+        apu.write_register(0x4013, 0x00);
+        apu.write_register(0x4015, STATUS_DMC);
+
+        // Bits play LSB first: four steps up, then four steps down
+        let mut peak = 0;
+        for _ in 0..54 * 8 * 4 {
+            run_dmc(&mut apu, 1, &[0b0000_1111]);
+            peak = peak.max(apu.dmc.output_level);
+        }
+        assert_eq!(peak, 64 + 8);
+        assert_eq!(apu.dmc.output_level, 64);
+    }
+
+    // Pulse 1 with a decaying envelope (period 0) and a long, unhalted length counter,
+    // so quarter frames show up as envelope steps and half frames as length steps
+    fn frame_probe(apu: &mut Apu) {
+        start_pulse_1(apu, 0b0000_0000, 1);
+    }
+
+    #[test]
+    fn four_step_sequence_cadence() {
+        let mut apu = Apu::new();
+        frame_probe(&mut apu);
+
+        apu.tick(FRAME_STEP_1);
+        assert_eq!(apu.pulse_1.envelope.output(), 15);
+        assert_eq!(apu.pulse_1.length.counter, 254);
+
+        apu.tick(FRAME_STEP_2 - FRAME_STEP_1);
+        assert_eq!(apu.pulse_1.envelope.output(), 14);
+        assert_eq!(apu.pulse_1.length.counter, 253);
+
+        apu.tick(FOUR_STEP_LAST - FRAME_STEP_2);
+        assert_eq!(apu.pulse_1.envelope.output(), 12);
+        assert_eq!(apu.pulse_1.length.counter, 252);
+
+        // Next frame starts over
+        apu.tick(FRAME_STEP_2);
+        assert_eq!(apu.pulse_1.envelope.output(), 10);
+        assert_eq!(apu.pulse_1.length.counter, 251);
+    }
+
+    #[test]
+    fn five_step_sequence_cadence() {
+        let mut apu = Apu::new();
+        frame_probe(&mut apu);
+
+        // The write itself clocks both units
+        apu.write_register(0x4017, FRAME_FIVE_STEP);
+        assert_eq!(apu.pulse_1.envelope.output(), 15);
+        assert_eq!(apu.pulse_1.length.counter, 253);
+
+        // Nothing happens where the 4-step sequence would end
+        apu.tick(FOUR_STEP_LAST);
+        assert_eq!(apu.pulse_1.envelope.output(), 12);
+        assert_eq!(apu.pulse_1.length.counter, 252);
+
+        apu.tick(FIVE_STEP_LAST - FOUR_STEP_LAST);
+        assert_eq!(apu.pulse_1.envelope.output(), 11);
+        assert_eq!(apu.pulse_1.length.counter, 251);
+    }
+
+    #[test]
+    fn frame_irq_fires_in_four_step_mode() {
+        let mut apu = Apu::new();
+        apu.tick(FOUR_STEP_LAST - 1);
+        assert!(!apu.irq());
+        apu.tick(1);
+        assert!(apu.irq());
+
+        // Reading the status acknowledges it
+        assert_eq!(apu.read_status() & STATUS_FRAME_IRQ, STATUS_FRAME_IRQ);
+        assert!(!apu.irq());
+    }
+
+    #[test]
+    fn frame_irq_inhibit_and_five_step_mode_suppress_the_irq() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4017, FRAME_IRQ_INHIBIT);
+        apu.tick(FOUR_STEP_LAST * 2);
+        assert!(!apu.irq());
+
+        let mut apu = Apu::new();
+        apu.write_register(0x4017, FRAME_FIVE_STEP);
+        apu.tick(FIVE_STEP_LAST * 2);
+        assert!(!apu.irq());
+
+        // Setting inhibit clears a pending IRQ
+        let mut apu = Apu::new();
+        apu.tick(FOUR_STEP_LAST);
+        apu.write_register(0x4017, FRAME_IRQ_INHIBIT);
+        assert!(!apu.irq());
+    }
+
+    #[test]
+    fn drain_audio_resamples_without_drift() {
+        let mut apu = Apu::new();
+        start_pulse_1(&mut apu, 0b0001_1111, 0);
+        let mut out = [0.0; 1024];
+        let mut produced = 0;
+
+        // A second's worth of frames, drained once per frame like a frontend would
+        for _ in 0..60 {
+            apu.tick(29781);
+            produced += apu.drain_audio(&mut out);
+        }
+
+        // 60 * 29781 cycles at 44.1kHz is 44028.2 samples
+        assert_eq!(produced, 44028);
+        assert!(out.iter().any(|&sample| sample > 0.0));
+        assert!(out.iter().all(|&sample| (0.0..=1.0).contains(&sample)));
+    }
+}
@@ -0,0 +1,660 @@
+use crate::apu::{Apu, DMC_DMA_CYCLES};
+use crate::cartridge::Rom;
+use crate::cpu::Mem;
+use crate::joypad::Joypad;
+use crate::mapper::{self, Mapper, SharedMapper};
+use crate::ppu::Ppu;
+use crate::state::{StateReader, StateWriter};
+use std::cell::RefCell;
+use std::ops::RangeInclusive;
+use std::rc::Rc;
+
+// 2KB of internal RAM, mirrored four times up to 0x1FFF
+const RAM: u16 = 0x0000;
+const RAM_MIRRORS_END: u16 = 0x1FFF;
+const RAM_MIRROR_MASK: u16 = 0x07FF;
+
+// The eight PPU registers repeat every 8 bytes up to 0x3FFF
+const PPU_REGISTERS: u16 = 0x2000;
+const PPU_REGISTERS_MIRRORS_END: u16 = 0x3FFF;
+
+// APU channel registers, plus status/enable at 0x4015
+const APU_REGISTERS: u16 = 0x4000;
+const APU_REGISTERS_END: u16 = 0x4013;
+const APU_STATUS: u16 = 0x4015;
+const APU_FRAME_COUNTER: u16 = 0x4017;
+
+// Writing N here copies CPU page N into OAM
+const OAM_DMA: u16 = 0x4014;
+const OAM_DMA_CYCLES: usize = 513;
+
+// Writing 0x4016 strobes both controllers; 0x4017 writes go to the APU frame counter
+const JOYPAD_1: u16 = 0x4016;
+const JOYPAD_2: u16 = 0x4017;
+
+// APU and I/O test-mode registers, disabled on retail consoles
+const IO_TEST_MODE: u16 = 0x4018;
+const IO_TEST_MODE_END: u16 = 0x401F;
+
+// Cartridge expansion area; none of the supported mappers decode it
+const EXPANSION: u16 = 0x4020;
+const EXPANSION_END: u16 = 0x5FFF;
+
+const PRG_ROM: u16 = 0x8000;
+const PRG_ROM_END: u16 = 0xFFFF;
+
+const MEMORY_SIZE: usize = 0x10000;
+
+// What internal RAM holds at power-on. Real consoles come up with noise that
+// varies between units; the seeded fill makes that reproducible.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PowerOnState {
+    #[default]
+    Zeros,
+    Ones,
+    Random(u64),
+}
+
+// xorshift64; the state must never be zero
+struct PowerOnRng(u64);
+
+impl PowerOnRng {
+    fn new(seed: u64) -> Self {
+        PowerOnRng(if seed == 0 {
+            0x9E37_79B9_7F4A_7C15
+        } else {
+            seed
+        })
+    }
+
+    fn next_byte(&mut self) -> u8 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 32) as u8
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+// Called with the address, the byte read or written, and which it was
+pub type WatchCallback = Box<dyn FnMut(u16, u8, Access)>;
+
+struct Watchpoint {
+    range: RangeInclusive<u16>,
+    callback: WatchCallback,
+}
+
+// Everything the CPU can address lives behind the bus.
+// Without a cartridge the upper half is plain RAM, which is what bare programs use.
+pub struct Bus {
+    memory: Box<[u8; MEMORY_SIZE]>,
+    mapper: Option<SharedMapper>,
+    pub ppu: Ppu,
+    pub apu: Apu,
+    pub joypad1: Joypad,
+    pub joypad2: Joypad,
+    nmi_pending: bool,
+    dma_stall: usize,
+    // Reads from addresses nothing drives see the last byte left on the data bus
+    last_bus_value: u8,
+    watchpoints: Vec<Watchpoint>,
+}
+
+// Built through a Vec so the 64KB array never lands on the stack
+fn zeroed_memory() -> Box<[u8; MEMORY_SIZE]> {
+    vec![0; MEMORY_SIZE]
+        .into_boxed_slice()
+        .try_into()
+        .expect("memory has a fixed size")
+}
+
+impl Default for Bus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bus {
+    pub fn new() -> Self {
+        Bus {
+            memory: zeroed_memory(),
+            mapper: None,
+            ppu: Ppu::new(None),
+            apu: Apu::new(),
+            joypad1: Joypad::new(),
+            joypad2: Joypad::new(),
+            nmi_pending: false,
+            dma_stall: 0,
+            last_bus_value: 0,
+            watchpoints: Vec::new(),
+        }
+    }
+
+    // Picks the mapper from the ROM header; fails on unsupported mapper numbers
+    pub fn with_rom(rom: Rom) -> Result<Self, String> {
+        Ok(Self::with_mapper(mapper::mapper_for_rom(rom)?))
+    }
+
+    pub fn with_mapper(mapper: Box<dyn Mapper>) -> Self {
+        let mapper: SharedMapper = Rc::new(RefCell::new(mapper));
+        Bus {
+            memory: zeroed_memory(),
+            ppu: Ppu::new(Some(mapper.clone())),
+            apu: Apu::new(),
+            joypad1: Joypad::new(),
+            joypad2: Joypad::new(),
+            mapper: Some(mapper),
+            nmi_pending: false,
+            dma_stall: 0,
+            last_bus_value: 0,
+            watchpoints: Vec::new(),
+        }
+    }
+
+    // Fills the 2KB of internal RAM the way the console might power up
+    pub fn power_on(&mut self, pattern: PowerOnState) {
+        let ram = &mut self.memory[..=RAM_MIRROR_MASK as usize];
+        match pattern {
+            PowerOnState::Zeros => ram.fill(0x00),
+            PowerOnState::Ones => ram.fill(0xFF),
+            PowerOnState::Random(seed) => {
+                let mut rng = PowerOnRng::new(seed);
+                ram.iter_mut().for_each(|byte| *byte = rng.next_byte());
+            }
+        }
+    }
+
+    // Raised by the PPU on entering vblank; the CPU services it before its next instruction
+    pub fn request_nmi(&mut self) {
+        self.nmi_pending = true;
+    }
+
+    // Returns whether an NMI is pending and acknowledges it
+    pub fn poll_nmi(&mut self) -> bool {
+        std::mem::take(&mut self.nmi_pending)
+    }
+
+    // The IRQ line is level-triggered: it stays asserted while the APU or the
+    // cartridge holds it, and drops as soon as the source is acknowledged
+    pub fn poll_irq(&self) -> bool {
+        let mapper_irq = self
+            .mapper
+            .as_ref()
+            .is_some_and(|mapper| mapper.borrow().irq());
+        self.apu.irq() || mapper_irq
+    }
+
+    // Advances the other chips by the CPU cycles just spent. The PPU runs three dots per cycle.
+    // The APU steps a cycle at a time so long batches (DMA stalls, skipped loops) still
+    // service every DMC sample fetch.
+    pub fn tick(&mut self, cycles: usize) {
+        for _ in 0..cycles {
+            self.apu.tick(1);
+            if let Some(addr) = self.apu.dmc_dma_request() {
+                let data = self.mem_read(addr);
+                self.apu.dmc_dma_fill(data);
+                self.dma_stall += DMC_DMA_CYCLES;
+            }
+        }
+        self.ppu.tick(cycles * 3);
+        if self.ppu.poll_nmi() {
+            self.request_nmi();
+        }
+    }
+
+    // CPU cycles owed for DMA since the last call
+    pub fn take_dma_stall(&mut self) -> usize {
+        std::mem::take(&mut self.dma_stall)
+    }
+
+    pub fn dma_stall_pending(&self) -> bool {
+        self.dma_stall > 0
+    }
+
+    fn oam_dma(&mut self, page: u8) {
+        let start = (page as u16) << 8;
+        let mut data = [0; 256];
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = self.mem_read(start + i as u16);
+        }
+
+        self.ppu.write_oam_dma(&data);
+        self.dma_stall += OAM_DMA_CYCLES;
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&self.memory[..]);
+        state.bool(self.mapper.is_some());
+        if let Some(mapper) = &self.mapper {
+            mapper.borrow().save_state(state);
+        }
+        self.ppu.save_state(state);
+        self.apu.save_state(state);
+        self.joypad1.save_state(state);
+        self.joypad2.save_state(state);
+        state.bool(self.nmi_pending);
+        state.usize(self.dma_stall);
+        state.u8(self.last_bus_value);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        state.bytes(&mut self.memory[..])?;
+        if state.bool()? != self.mapper.is_some() {
+            return Err("Save state was made with a different cartridge setup".to_string());
+        }
+        if let Some(mapper) = &self.mapper {
+            mapper.borrow_mut().load_state(state)?;
+        }
+        self.ppu.load_state(state)?;
+        self.apu.load_state(state)?;
+        self.joypad1.load_state(state)?;
+        self.joypad2.load_state(state)?;
+        self.nmi_pending = state.bool()?;
+        self.dma_stall = state.usize()?;
+        self.last_bus_value = state.u8()?;
+        Ok(())
+    }
+
+    // Watches CPU reads and writes to `range`. Side-effect-free peeks are not reported.
+    pub fn add_watchpoint<F>(&mut self, range: RangeInclusive<u16>, callback: F)
+    where
+        F: FnMut(u16, u8, Access) + 'static,
+    {
+        self.watchpoints.push(Watchpoint {
+            range,
+            callback: Box::new(callback),
+        });
+    }
+
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints.clear();
+    }
+
+    fn notify_watchpoints(&mut self, address: u16, data: u8, access: Access) {
+        for watchpoint in &mut self.watchpoints {
+            if watchpoint.range.contains(&address) {
+                (watchpoint.callback)(address, data, access);
+            }
+        }
+    }
+
+    fn read(&mut self, address: u16) -> u8 {
+        match (&self.mapper, address) {
+            (_, PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END) => self.ppu.read_register(address),
+            (_, APU_STATUS) => self.apu.read_status(),
+            (_, JOYPAD_1) => self.joypad1.read(),
+            (_, JOYPAD_2) => self.joypad2.read(),
+            (_, APU_REGISTERS..=OAM_DMA | IO_TEST_MODE..=IO_TEST_MODE_END) => self.last_bus_value,
+            (Some(_), EXPANSION..=EXPANSION_END) => self.last_bus_value,
+            (Some(mapper), PRG_ROM..=PRG_ROM_END) => mapper.borrow().cpu_read(address),
+            _ => self.memory[Self::mirror_address(address) as usize],
+        }
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        match (&self.mapper, address) {
+            (_, PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END) => {
+                self.ppu.write_register(address, data)
+            }
+            (_, APU_REGISTERS..=APU_REGISTERS_END | APU_STATUS | APU_FRAME_COUNTER) => {
+                self.apu.write_register(address, data)
+            }
+            (_, OAM_DMA) => self.oam_dma(data),
+            (_, JOYPAD_1) => {
+                self.joypad1.write(data);
+                self.joypad2.write(data);
+            }
+            (Some(mapper), PRG_ROM..=PRG_ROM_END) => mapper.borrow_mut().cpu_write(address, data),
+            (Some(_), EXPANSION..=EXPANSION_END) => {}
+            _ => self.memory[Self::mirror_address(address) as usize] = data,
+        }
+    }
+
+    fn mirror_address(address: u16) -> u16 {
+        match address {
+            RAM..=RAM_MIRRORS_END => address & RAM_MIRROR_MASK,
+            _ => address,
+        }
+    }
+}
+
+impl Mem for Bus {
+    fn mem_read(&mut self, address: u16) -> u8 {
+        let data = self.read(address);
+        self.last_bus_value = data;
+        if !self.watchpoints.is_empty() {
+            self.notify_watchpoints(address, data, Access::Read);
+        }
+        data
+    }
+
+    fn mem_peek(&self, address: u16) -> u8 {
+        match (&self.mapper, address) {
+            (_, PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END) => self.ppu.peek_register(address),
+            (_, APU_STATUS) => self.apu.peek_status(),
+            (_, JOYPAD_1) => self.joypad1.peek(),
+            (_, JOYPAD_2) => self.joypad2.peek(),
+            (_, APU_REGISTERS..=OAM_DMA | IO_TEST_MODE..=IO_TEST_MODE_END) => self.last_bus_value,
+            (Some(_), EXPANSION..=EXPANSION_END) => self.last_bus_value,
+            (Some(mapper), PRG_ROM..=PRG_ROM_END) => mapper.borrow().cpu_read(address),
+            _ => self.memory[Self::mirror_address(address) as usize],
+        }
+    }
+
+    fn mem_write(&mut self, address: u16, data: u8) {
+        if !self.watchpoints.is_empty() {
+            self.notify_watchpoints(address, data, Access::Write);
+        }
+        self.write(address, data);
+    }
+
+    fn tick(&mut self, cycles: usize) {
+        Bus::tick(self, cycles);
+    }
+
+    fn poll_nmi(&mut self) -> bool {
+        Bus::poll_nmi(self)
+    }
+
+    fn poll_irq(&self) -> bool {
+        Bus::poll_irq(self)
+    }
+
+    fn take_dma_stall(&mut self) -> usize {
+        Bus::take_dma_stall(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::Mirroring;
+    use crate::mapper::test::test_rom;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // Records what the bus forwards so dispatch can be checked
+    struct DummyMapper {
+        writes: Rc<RefCell<Vec<(u16, u8)>>>,
+    }
+
+    impl Mapper for DummyMapper {
+        fn cpu_read(&self, address: u16) -> u8 {
+            (address >> 8) as u8
+        }
+
+        fn cpu_write(&mut self, address: u16, data: u8) {
+            self.writes.borrow_mut().push((address, data));
+        }
+
+        fn ppu_read(&self, _address: u16) -> u8 {
+            0
+        }
+
+        fn ppu_write(&mut self, _address: u16, _data: u8) {}
+
+        fn mirroring(&self) -> Mirroring {
+            Mirroring::Horizontal
+        }
+    }
+
+    #[test]
+    fn ram_is_mirrored_up_to_0x1fff() {
+        let mut bus = Bus::new();
+        bus.mem_write(0x0000, 0x42);
+
+        assert_eq!(bus.mem_read(0x0800), 0x42);
+        assert_eq!(bus.mem_read(0x1000), 0x42);
+        assert_eq!(bus.mem_read(0x1800), 0x42);
+
+        bus.mem_write(0x1FFF, 0x99);
+        assert_eq!(bus.mem_read(0x07FF), 0x99);
+    }
+
+    #[test]
+    fn addresses_above_ram_are_not_mirrored() {
+        let mut bus = Bus::new();
+        bus.mem_write(0x2000, 0x11);
+        bus.mem_write(0x8000, 0x22);
+
+        assert_eq!(bus.mem_read(0x0000), 0x00);
+        assert_eq!(bus.mem_read(0x8000), 0x22);
+    }
+
+    #[test]
+    fn bus_dispatches_cartridge_space_to_mapper() {
+        let writes = Rc::new(RefCell::new(Vec::new()));
+        let mut bus = Bus::with_mapper(Box::new(DummyMapper {
+            writes: writes.clone(),
+        }));
+
+        assert_eq!(bus.mem_read(0x8000), 0x80);
+        assert_eq!(bus.mem_read(0xFFFF), 0xFF);
+        bus.mem_write(0xC123, 0x07);
+        bus.mem_write(0x0010, 0x08); // RAM, not the mapper
+
+        assert_eq!(*writes.borrow(), vec![(0xC123, 0x07)]);
+        assert_eq!(bus.mem_read(0x0010), 0x08);
+    }
+
+    #[test]
+    fn reset_vector_is_read_from_prg_rom() {
+        let mut prg = vec![0xEA; 0x4000];
+        prg[0x3FFC] = 0x00;
+        prg[0x3FFD] = 0xC0;
+        let mut bus = Bus::with_rom(test_rom(prg, vec![0; 0x2000])).unwrap();
+
+        assert_eq!(bus.mem_read_u16(0xFFFC), 0xC000);
+    }
+
+    #[test]
+    fn writes_to_prg_rom_are_ignored() {
+        let mut bus = Bus::with_rom(test_rom(vec![0x5A; 0x4000], vec![0; 0x2000])).unwrap();
+        bus.mem_write(0x8000, 0xFF);
+
+        assert_eq!(bus.mem_read(0x8000), 0x5A);
+    }
+
+    #[test]
+    fn with_rom_uses_the_mapper_from_the_header() {
+        let mut prg = vec![0x11; 0x4000];
+        prg.extend(vec![0x22; 0x4000]);
+        let mut bus =
+            Bus::with_rom(Rom::new(prg, vec![0; 0x2000], 2, Mirroring::Vertical)).unwrap();
+
+        // UxROM: selecting bank 1 swaps it into $8000
+        bus.mem_write(0x8000, 0x01);
+        assert_eq!(bus.mem_read(0x8000), 0x22);
+
+        let rom = Rom::new(vec![0; 0x4000], vec![0; 0x2000], 4, Mirroring::Vertical);
+        assert!(Bus::with_rom(rom).is_err());
+    }
+
+    #[test]
+    fn ppu_registers_are_mirrored_every_8_bytes() {
+        let mut chr = vec![0; 0x2000];
+        chr[0x0123] = 0x5C;
+        let mut bus = Bus::with_rom(test_rom(vec![0; 0x4000], chr)).unwrap();
+
+        // PPUADDR through its mirrors at 0x3FFE and 0x200E
+        bus.mem_write(0x3FFE, 0x01);
+        bus.mem_write(0x200E, 0x23);
+        bus.mem_read(0x2007);
+
+        // Pattern data comes from the cartridge through the shared mapper
+        assert_eq!(bus.mem_read(0x2FFF), 0x5C);
+    }
+
+    #[test]
+    fn oam_dma_copies_a_cpu_page_into_oam() {
+        let mut bus = Bus::new();
+        for i in 0..=0xFF {
+            bus.mem_write(0x0200 + i, i as u8 ^ 0xA5);
+        }
+
+        bus.mem_write(0x4014, 0x02);
+        assert_eq!(bus.take_dma_stall(), 513);
+        assert_eq!(bus.take_dma_stall(), 0);
+
+        for i in 0..=0xFF {
+            bus.mem_write(0x2003, i);
+            assert_eq!(bus.mem_read(0x2004), i ^ 0xA5);
+        }
+    }
+
+    #[test]
+    fn joypad_is_read_through_0x4016() {
+        let mut bus = Bus::new();
+        bus.joypad1
+            .set_button_pressed(crate::joypad::BUTTON_B, true);
+
+        bus.mem_write(0x4016, 1);
+        bus.mem_write(0x4016, 0);
+
+        assert_eq!(bus.mem_read(0x4016), 0);
+        assert_eq!(bus.mem_peek(0x4016), 1);
+        assert_eq!(bus.mem_read(0x4016), 1);
+        assert_eq!(bus.mem_read(0x4016), 0);
+    }
+
+    #[test]
+    fn second_joypad_is_read_through_0x4017() {
+        let mut bus = Bus::new();
+        bus.joypad2
+            .set_button_pressed(crate::joypad::BUTTON_SELECT, true);
+        let apu_state = |bus: &Bus| {
+            let mut state = StateWriter::new();
+            bus.apu.save_state(&mut state);
+            state.into_bytes()
+        };
+        let before = apu_state(&bus);
+
+        // One strobe latches both controllers
+        bus.mem_write(0x4016, 1);
+        bus.mem_write(0x4016, 0);
+
+        let bits: Vec<u8> = (0..8).map(|_| bus.mem_read(0x4017)).collect();
+        assert_eq!(bits, vec![0, 0, 1, 0, 0, 0, 0, 0]);
+        assert_eq!(bus.mem_read(0x4016), 0);
+        assert_eq!(apu_state(&bus), before);
+
+        // Writes still reach the frame counter, not the controller
+        bus.mem_write(0x4017, 0b1000_0000);
+        assert_ne!(apu_state(&bus), before);
+        assert_eq!(bus.mem_read(0x4017), 1);
+    }
+
+    #[test]
+    fn seeded_power_on_is_reproducible() {
+        let ram = |pattern| {
+            let mut bus = Bus::new();
+            bus.power_on(pattern);
+            (0..0x800)
+                .map(|addr| bus.mem_peek(addr))
+                .collect::<Vec<u8>>()
+        };
+
+        let first = ram(PowerOnState::Random(0x1234));
+        assert_eq!(first, ram(PowerOnState::Random(0x1234)));
+        assert_ne!(first, ram(PowerOnState::Random(0x4321)));
+        assert!(first.iter().any(|&byte| byte != first[0]));
+
+        assert!(ram(PowerOnState::Ones).iter().all(|&byte| byte == 0xFF));
+        assert!(ram(PowerOnState::default()).iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn apu_registers_are_mapped() {
+        let mut bus = Bus::new();
+        bus.mem_write(0x4015, 0b0000_0010);
+        bus.mem_write(0x4007, 0b0000_1000);
+
+        assert_eq!(bus.mem_read(0x4015), 0b0000_0010);
+    }
+
+    #[test]
+    fn dmc_sample_fetch_stalls_the_cpu() {
+        let mut bus = Bus::new();
+        bus.mem_write(0x4013, 0x00); // 1 byte sample at $C000
+        bus.mem_write(0x4015, 0b0001_0000);
+
+        bus.tick(1);
+        assert_eq!(bus.take_dma_stall(), 4);
+        bus.tick(1);
+        assert_eq!(bus.take_dma_stall(), 0);
+    }
+
+    #[test]
+    fn long_ticks_service_every_dmc_fetch() {
+        let start_dmc = |bus: &mut Bus| {
+            bus.mem_write(0x4010, 0x0F); // Fastest rate: a byte every 54 * 8 cycles
+            bus.mem_write(0x4013, 0xFF); // 4081 byte sample at $C000
+            bus.mem_write(0x4015, 0b0001_0000);
+        };
+        let cycles = 54 * 8 * 20;
+
+        let mut stepped = Bus::new();
+        start_dmc(&mut stepped);
+        for _ in 0..cycles {
+            stepped.tick(1);
+        }
+
+        let mut batched = Bus::new();
+        start_dmc(&mut batched);
+        batched.tick(cycles);
+
+        let fetches = batched.take_dma_stall() / DMC_DMA_CYCLES;
+        assert!(fetches >= 20, "only {} fetches", fetches);
+        assert_eq!(fetches, stepped.take_dma_stall() / DMC_DMA_CYCLES);
+    }
+
+    #[test]
+    fn watchpoint_reports_cpu_accesses() {
+        let hits = Rc::new(RefCell::new(Vec::new()));
+        let log = hits.clone();
+        let mut cpu = crate::cpu::CPU::new();
+        cpu.bus
+            .add_watchpoint(0x10..=0x10, move |address, data, access| {
+                log.borrow_mut().push((address, data, access));
+            });
+
+        // LDA #$42; STA $10; LDX $10; STA $11
+        cpu.load_and_run(vec![0xA9, 0x42, 0x85, 0x10, 0xA6, 0x10, 0x85, 0x11, 0x00]);
+
+        assert_eq!(
+            *hits.borrow(),
+            vec![(0x10, 0x42, Access::Write), (0x10, 0x42, Access::Read)]
+        );
+    }
+
+    #[test]
+    fn unmapped_reads_return_the_last_bus_value() {
+        let mut bus = Bus::new();
+        bus.mem_write(0x0200, 0x5A);
+
+        assert_eq!(bus.mem_read(0x0200), 0x5A);
+        assert_eq!(bus.mem_read(0x401A), 0x5A);
+        assert_eq!(bus.mem_peek(0x4000), 0x5A);
+
+        bus.mem_write(0x0201, 0xC3);
+        bus.mem_read(0x0201);
+        assert_eq!(bus.mem_read(0x4018), 0xC3);
+    }
+
+    #[test]
+    fn cartridge_expansion_area_is_open_bus() {
+        let mut bus = Bus::with_rom(test_rom(vec![0; 0x4000], vec![0; 0x2000])).unwrap();
+        bus.mem_write(0x0200, 0x5A);
+        bus.mem_read(0x0200);
+
+        bus.mem_write(0x5000, 0x77);
+        assert_eq!(bus.mem_read(0x5000), 0x5A);
+        assert_eq!(bus.mem_peek(0x4020), 0x5A);
+        assert_eq!(bus.mem_peek(0x5FFF), 0x5A);
+    }
+}
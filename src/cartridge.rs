@@ -0,0 +1,241 @@
+#![allow(dead_code)]
+
+const NES_TAG: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A]; // "NES" followed by MS-DOS EOF
+const HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 512;
+const PRG_ROM_PAGE_SIZE: usize = 16384;
+const CHR_ROM_PAGE_SIZE: usize = 8192;
+pub const CHR_RAM_SIZE: usize = 0x2000;
+
+// Header flag 6 bits
+const FLAG_VERTICAL_MIRRORING: u8 = 0b0000_0001;
+const FLAG_TRAINER: u8 = 0b0000_0100;
+const FLAG_FOUR_SCREEN: u8 = 0b0000_1000;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Mirroring {
+    Vertical,
+    Horizontal,
+    FourScreen,
+    // Mapper-controlled: every nametable shows the same 1KB page
+    SingleScreenLower,
+    SingleScreenUpper,
+}
+
+pub struct Rom {
+    pub prg_rom: Vec<u8>,
+    // Zero-filled CHR RAM when chr_ram is set
+    pub chr_rom: Vec<u8>,
+    pub chr_ram: bool,
+    pub mapper: u8,
+    pub screen_mirroring: Mirroring,
+}
+
+impl Rom {
+    // Boards without CHR ROM carry 8KB of CHR RAM in its place
+    pub fn new(prg_rom: Vec<u8>, chr_rom: Vec<u8>, mapper: u8, screen_mirroring: Mirroring) -> Rom {
+        let chr_ram = chr_rom.is_empty();
+        let chr_rom = if chr_ram {
+            vec![0; CHR_RAM_SIZE]
+        } else {
+            chr_rom
+        };
+
+        Rom {
+            prg_rom,
+            chr_rom,
+            chr_ram,
+            mapper,
+            screen_mirroring,
+        }
+    }
+
+    pub fn from_bytes(raw: &[u8]) -> Result<Rom, String> {
+        if raw.len() < HEADER_SIZE {
+            return Err(format!(
+                "File is too short for an iNES header: {} bytes",
+                raw.len()
+            ));
+        }
+
+        if raw[0..4] != NES_TAG {
+            return Err("File is not in iNES file format".to_string());
+        }
+
+        let flags_6 = raw[6];
+        let flags_7 = raw[7];
+        let mapper = (flags_7 & 0b1111_0000) | (flags_6 >> 4);
+
+        let screen_mirroring = if flags_6 & FLAG_FOUR_SCREEN != 0 {
+            Mirroring::FourScreen
+        } else if flags_6 & FLAG_VERTICAL_MIRRORING != 0 {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        };
+
+        if raw[4] == 0 {
+            return Err("Header declares no PRG ROM banks".to_string());
+        }
+
+        let prg_rom_size = raw[4] as usize * PRG_ROM_PAGE_SIZE;
+        let chr_rom_size = raw[5] as usize * CHR_ROM_PAGE_SIZE;
+
+        let skip_trainer = flags_6 & FLAG_TRAINER != 0;
+        let prg_rom_start = HEADER_SIZE + if skip_trainer { TRAINER_SIZE } else { 0 };
+        let chr_rom_start = prg_rom_start + prg_rom_size;
+        let expected_len = chr_rom_start + chr_rom_size;
+
+        if raw.len() < expected_len {
+            return Err(format!(
+                "ROM is truncated: header needs {} bytes but file has {}",
+                expected_len,
+                raw.len()
+            ));
+        }
+
+        Ok(Rom::new(
+            raw[prg_rom_start..chr_rom_start].to_vec(),
+            raw[chr_rom_start..expected_len].to_vec(),
+            mapper,
+            screen_mirroring,
+        ))
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    pub struct TestRom {
+        pub header: Vec<u8>,
+        pub trainer: Option<Vec<u8>>,
+        pub prg_rom: Vec<u8>,
+        pub chr_rom: Vec<u8>,
+    }
+
+    pub fn create_rom(rom: TestRom) -> Vec<u8> {
+        let mut result = Vec::with_capacity(
+            rom.header.len()
+                + rom.trainer.as_ref().map_or(0, |t| t.len())
+                + rom.prg_rom.len()
+                + rom.chr_rom.len(),
+        );
+
+        result.extend(&rom.header);
+        if let Some(t) = rom.trainer {
+            result.extend(t);
+        }
+        result.extend(&rom.prg_rom);
+        result.extend(&rom.chr_rom);
+
+        result
+    }
+
+    #[test]
+    fn test_parses_minimal_rom() {
+        let raw = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, 0x31, 0x00, 0, 0, 0, 0, 0, 0, 0, 0,
+            ],
+            trainer: None,
+            prg_rom: vec![1; 2 * PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+
+        let rom = Rom::from_bytes(&raw).unwrap();
+
+        assert_eq!(rom.chr_rom, vec![2; CHR_ROM_PAGE_SIZE]);
+        assert_eq!(rom.prg_rom, vec![1; 2 * PRG_ROM_PAGE_SIZE]);
+        assert_eq!(rom.mapper, 3);
+        assert_eq!(rom.screen_mirroring, Mirroring::Vertical);
+    }
+
+    #[test]
+    fn test_skips_trainer() {
+        let raw = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x04, 0x00, 0, 0, 0, 0, 0, 0, 0, 0,
+            ],
+            trainer: Some(vec![0xFF; TRAINER_SIZE]),
+            prg_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+
+        let rom = Rom::from_bytes(&raw).unwrap();
+
+        assert_eq!(rom.prg_rom, vec![1; PRG_ROM_PAGE_SIZE]);
+        assert_eq!(rom.chr_rom, vec![2; CHR_ROM_PAGE_SIZE]);
+        assert_eq!(rom.mapper, 0);
+        assert_eq!(rom.screen_mirroring, Mirroring::Horizontal);
+    }
+
+    #[test]
+    fn test_rejects_bad_magic() {
+        let raw = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x00, 0x01, 0x01, 0x00, 0x00, 0, 0, 0, 0, 0, 0, 0, 0,
+            ],
+            trainer: None,
+            prg_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+
+        match Rom::from_bytes(&raw) {
+            Err(e) => assert_eq!(e, "File is not in iNES file format"),
+            Ok(_) => panic!("bad magic should be rejected"),
+        }
+    }
+
+    #[test]
+    fn test_zero_chr_banks_means_chr_ram() {
+        let raw = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x01, 0x00, 0x20, 0x00, 0, 0, 0, 0, 0, 0, 0, 0,
+            ],
+            trainer: None,
+            prg_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![],
+        });
+
+        let rom = Rom::from_bytes(&raw).unwrap();
+        assert!(rom.chr_ram);
+        assert_eq!(rom.chr_rom, vec![0; CHR_RAM_SIZE]);
+        assert_eq!(rom.mapper, 2);
+    }
+
+    #[test]
+    fn test_rejects_truncated_rom() {
+        let raw = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, 0x00, 0x00, 0, 0, 0, 0, 0, 0, 0, 0,
+            ],
+            trainer: None,
+            prg_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![],
+        });
+
+        match Rom::from_bytes(&raw) {
+            Err(e) => assert!(e.contains("truncated"), "{}", e),
+            Ok(_) => panic!("truncated ROM should be rejected"),
+        }
+        assert!(Rom::from_bytes(&raw[0..8]).is_err());
+    }
+
+    #[test]
+    fn test_rejects_missing_prg_rom() {
+        let raw = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x00, 0x01, 0x00, 0x00, 0, 0, 0, 0, 0, 0, 0, 0,
+            ],
+            trainer: None,
+            prg_rom: vec![],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+
+        match Rom::from_bytes(&raw) {
+            Err(e) => assert!(e.contains("no PRG ROM"), "{}", e),
+            Ok(_) => panic!("ROM without PRG banks should be rejected"),
+        }
+    }
+}
@@ -0,0 +1,107 @@
+#![allow(dead_code)]
+
+use crate::cpu::{CPU, CpuError};
+use std::collections::HashSet;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    // PC reached a breakpoint; the instruction there has not run yet
+    Breakpoint(u16),
+    Halted,
+}
+
+// Runs a CPU with PC breakpoints that can be changed between runs
+pub struct Debugger {
+    pub cpu: CPU,
+    breakpoints: HashSet<u16>,
+}
+
+impl Debugger {
+    pub fn new(cpu: CPU) -> Self {
+        Debugger {
+            cpu,
+            breakpoints: HashSet::new(),
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr);
+    }
+
+    // Returns whether a breakpoint was set at `addr`
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    pub fn breakpoints(&self) -> &HashSet<u16> {
+        &self.breakpoints
+    }
+
+    // Executes until PC lands on a breakpoint or the CPU halts. The first instruction
+    // always runs, so calling this again resumes past the breakpoint it stopped on.
+    pub fn run_until_break(&mut self) -> Result<StopReason, CpuError> {
+        loop {
+            if self.cpu.step()? {
+                return Ok(StopReason::Halted);
+            }
+            let pc = self.cpu.program_counter;
+            if self.breakpoints.contains(&pc) {
+                return Ok(StopReason::Breakpoint(pc));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::CpuBuilder;
+
+    fn debugger() -> Debugger {
+        let cpu = CpuBuilder::new()
+            .program(vec![
+                0xA9, 0x01, // 8000: LDA #$01
+                0xA2, 0x02, // 8002: LDX #$02
+                0xA0, 0x03, // 8004: LDY #$03
+                0xE8, //       8006: INX
+                0x00, //       8007: BRK
+            ])
+            .build();
+        Debugger::new(cpu)
+    }
+
+    #[test]
+    fn pauses_at_breakpoint_before_executing_it() {
+        let mut debugger = debugger();
+        debugger.add_breakpoint(0x8004);
+
+        assert_eq!(
+            debugger.run_until_break(),
+            Ok(StopReason::Breakpoint(0x8004))
+        );
+        assert_eq!(debugger.cpu.program_counter, 0x8004);
+        assert_eq!(debugger.cpu.register_a, 0x01);
+        assert_eq!(debugger.cpu.register_x, 0x02);
+        assert_eq!(debugger.cpu.register_y, 0x00);
+
+        assert_eq!(debugger.run_until_break(), Ok(StopReason::Halted));
+        assert_eq!(debugger.cpu.register_y, 0x03);
+        assert_eq!(debugger.cpu.register_x, 0x03);
+    }
+
+    #[test]
+    fn breakpoints_can_be_changed_between_runs() {
+        let mut debugger = debugger();
+        debugger.add_breakpoint(0x8002);
+        debugger.add_breakpoint(0x8006);
+
+        assert_eq!(
+            debugger.run_until_break(),
+            Ok(StopReason::Breakpoint(0x8002))
+        );
+        assert!(debugger.remove_breakpoint(0x8006));
+        assert!(!debugger.remove_breakpoint(0x8006));
+
+        assert_eq!(debugger.run_until_break(), Ok(StopReason::Halted));
+    }
+}
@@ -0,0 +1,76 @@
+#![allow(dead_code)]
+
+// One rendered picture: 256x240 pixels, 3 bytes (RGB) each, row by row
+pub struct Frame {
+    pub data: Vec<u8>,
+}
+
+impl Default for Frame {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Frame {
+    pub const WIDTH: usize = 256;
+    pub const HEIGHT: usize = 240;
+
+    pub fn new() -> Self {
+        Frame {
+            data: vec![0; Frame::WIDTH * Frame::HEIGHT * 3],
+        }
+    }
+
+    // Pixels outside the picture are dropped, so sprites can hang off the edges
+    pub fn set_pixel(&mut self, x: usize, y: usize, rgb: (u8, u8, u8)) {
+        if x >= Frame::WIDTH || y >= Frame::HEIGHT {
+            return;
+        }
+        let base = (y * Frame::WIDTH + x) * 3;
+        self.data[base] = rgb.0;
+        self.data[base + 1] = rgb.1;
+        self.data[base + 2] = rgb.2;
+    }
+
+    // Panics outside the picture
+    pub fn get_pixel(&self, x: usize, y: usize) -> (u8, u8, u8) {
+        assert!(
+            x < Frame::WIDTH && y < Frame::HEIGHT,
+            "pixel ({}, {}) out of range",
+            x,
+            y
+        );
+        let base = (y * Frame::WIDTH + x) * 3;
+        (self.data[base], self.data[base + 1], self.data[base + 2])
+    }
+
+    // Raw RGB24 rows, top to bottom, ready for an image encoder or a texture upload
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn set_pixel_writes_rgb_at_row_major_offset() {
+        let mut frame = Frame::new();
+        frame.set_pixel(2, 1, (10, 20, 30));
+
+        assert_eq!(frame.get_pixel(2, 1), (10, 20, 30));
+        let base = (Frame::WIDTH + 2) * 3;
+        assert_eq!(&frame.as_bytes()[base..base + 3], &[10, 20, 30]);
+        assert_eq!(frame.as_bytes().len(), Frame::WIDTH * Frame::HEIGHT * 3);
+    }
+
+    #[test]
+    fn set_pixel_ignores_out_of_range() {
+        let mut frame = Frame::new();
+        frame.set_pixel(Frame::WIDTH, 0, (1, 2, 3));
+        frame.set_pixel(0, Frame::HEIGHT, (1, 2, 3));
+
+        assert!(frame.as_bytes().iter().all(|&b| b == 0));
+    }
+}
@@ -0,0 +1,208 @@
+#![allow(dead_code)]
+
+use crate::state::{StateReader, StateWriter};
+
+// Button bits, in the order the controller shifts them out
+pub const BUTTON_A: u8 = 0b0000_0001;
+pub const BUTTON_B: u8 = 0b0000_0010;
+pub const BUTTON_SELECT: u8 = 0b0000_0100;
+pub const BUTTON_START: u8 = 0b0000_1000;
+pub const BUTTON_UP: u8 = 0b0001_0000;
+pub const BUTTON_DOWN: u8 = 0b0010_0000;
+pub const BUTTON_LEFT: u8 = 0b0100_0000;
+pub const BUTTON_RIGHT: u8 = 0b1000_0000;
+
+// Same order as the BUTTON_* bits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoypadButton {
+    A,
+    B,
+    Select,
+    Start,
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl JoypadButton {
+    pub const ALL: [JoypadButton; 8] = [
+        JoypadButton::A,
+        JoypadButton::B,
+        JoypadButton::Select,
+        JoypadButton::Start,
+        JoypadButton::Up,
+        JoypadButton::Down,
+        JoypadButton::Left,
+        JoypadButton::Right,
+    ];
+
+    // Position in the controller's shift register
+    pub fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+// Which NES button each frontend button drives. Starts out as the identity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ButtonMap {
+    targets: [JoypadButton; 8],
+}
+
+impl Default for ButtonMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ButtonMap {
+    pub fn new() -> Self {
+        ButtonMap {
+            targets: JoypadButton::ALL,
+        }
+    }
+
+    pub fn set(&mut self, input: JoypadButton, target: JoypadButton) {
+        self.targets[input as usize] = target;
+    }
+
+    pub fn get(&self, input: JoypadButton) -> JoypadButton {
+        self.targets[input as usize]
+    }
+}
+
+// Standard controller. Writing 1 to 0x4016 holds the strobe and keeps reporting A;
+// writing 0 releases it and each read then shifts out the next button.
+#[derive(Default)]
+pub struct Joypad {
+    strobe: bool,
+    button_index: u8,
+    buttons: u8,
+    mapping: ButtonMap,
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Joypad::default()
+    }
+
+    pub fn with_mapping(mapping: ButtonMap) -> Self {
+        Joypad {
+            mapping,
+            ..Joypad::default()
+        }
+    }
+
+    // `button` is the frontend's button; the mapping decides which bit it sets
+    pub fn press(&mut self, button: JoypadButton) {
+        self.set_button_pressed(self.mapping.get(button).bit(), true);
+    }
+
+    pub fn release(&mut self, button: JoypadButton) {
+        self.set_button_pressed(self.mapping.get(button).bit(), false);
+    }
+
+    pub fn set_button_pressed(&mut self, button: u8, pressed: bool) {
+        if pressed {
+            self.buttons |= button;
+        } else {
+            self.buttons &= !button;
+        }
+    }
+
+    pub fn write(&mut self, data: u8) {
+        self.strobe = data & 1 == 1;
+        if self.strobe {
+            self.button_index = 0;
+        }
+    }
+
+    pub fn read(&mut self) -> u8 {
+        let response = self.peek();
+        if !self.strobe && self.button_index < 8 {
+            self.button_index += 1;
+        }
+        response
+    }
+
+    // Official controllers report 1 once all eight buttons have been read
+    pub fn peek(&self) -> u8 {
+        if self.button_index > 7 {
+            return 1;
+        }
+        (self.buttons >> self.button_index) & 1
+    }
+
+    // The button mapping is frontend configuration and is not part of the state
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.bool(self.strobe);
+        state.u8(self.button_index);
+        state.u8(self.buttons);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.strobe = state.bool()?;
+        self.button_index = state.u8()?;
+        self.buttons = state.u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn strobe_then_read_buttons_in_order() {
+        let mut joypad = Joypad::new();
+        joypad.set_button_pressed(BUTTON_A, true);
+        joypad.set_button_pressed(BUTTON_START, true);
+        joypad.set_button_pressed(BUTTON_LEFT, true);
+        joypad.set_button_pressed(BUTTON_LEFT, false);
+        joypad.set_button_pressed(BUTTON_RIGHT, true);
+
+        joypad.write(1);
+        joypad.write(0);
+        let bits: Vec<u8> = (0..8).map(|_| joypad.read()).collect();
+        assert_eq!(bits, vec![1, 0, 0, 1, 0, 0, 0, 1]);
+
+        // Exhausted
+        assert_eq!(joypad.read(), 1);
+    }
+
+    #[test]
+    fn strobe_held_keeps_returning_a() {
+        let mut joypad = Joypad::new();
+        joypad.set_button_pressed(BUTTON_A, true);
+        joypad.write(1);
+
+        for _ in 0..10 {
+            assert_eq!(joypad.read(), 1);
+        }
+
+        joypad.set_button_pressed(BUTTON_A, false);
+        assert_eq!(joypad.read(), 0);
+    }
+
+    #[test]
+    fn remapped_buttons_shift_out_in_hardware_order() {
+        let mut mapping = ButtonMap::new();
+        mapping.set(JoypadButton::A, JoypadButton::B);
+        mapping.set(JoypadButton::B, JoypadButton::A);
+        mapping.set(JoypadButton::Select, JoypadButton::Right);
+        let mut joypad = Joypad::with_mapping(mapping);
+
+        joypad.press(JoypadButton::A);
+        joypad.press(JoypadButton::Select);
+        joypad.write(1);
+        joypad.write(0);
+        let bits: Vec<u8> = (0..8).map(|_| joypad.read()).collect();
+        assert_eq!(bits, vec![0, 1, 0, 0, 0, 0, 0, 1]);
+
+        joypad.release(JoypadButton::A);
+        joypad.write(1);
+        joypad.write(0);
+        let bits: Vec<u8> = (0..8).map(|_| joypad.read()).collect();
+        assert_eq!(bits, vec![0, 0, 0, 0, 0, 0, 0, 1]);
+    }
+}
@@ -1,9 +1,149 @@
+pub mod apu;
+pub mod bus;
+pub mod cartridge;
 pub mod cpu;
+pub mod debugger;
+pub mod frame;
+pub mod joypad;
+pub mod mapper;
 pub mod opcodes;
+pub mod palette;
+pub mod ppu;
+pub mod rewind;
+pub mod state;
+pub mod test_rom;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub use cpu::Mem;
 
 #[macro_use]
 extern crate lazy_static;
 
-fn main() {
-    println!("Hello, Emulator!");
+use bus::Bus;
+use cartridge::Rom;
+use cpu::CPU;
+use std::io::{BufWriter, Write};
+use std::process::ExitCode;
+
+const USAGE: &str = "usage: nes-following <rom.nes> [--trace] [--frames N]";
+const DEFAULT_FRAMES: u64 = 60;
+
+#[derive(Debug, PartialEq)]
+struct Options {
+    rom_path: String,
+    trace: bool,
+    frames: u64,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut rom_path = None;
+    let mut trace = false;
+    let mut frames = DEFAULT_FRAMES;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--trace" => trace = true,
+            "--frames" => {
+                let value = args.next().ok_or("--frames needs a count")?;
+                frames = value
+                    .parse()
+                    .map_err(|_| format!("Invalid frame count: {}", value))?;
+            }
+            flag if flag.starts_with("--") => return Err(format!("Unknown option: {}", flag)),
+            path if rom_path.is_none() => rom_path = Some(path.to_string()),
+            extra => return Err(format!("Unexpected argument: {}", extra)),
+        }
+    }
+
+    Ok(Options {
+        rom_path: rom_path.ok_or(USAGE)?,
+        trace,
+        frames,
+    })
+}
+
+// Runs the ROM for the requested number of frames. With --trace every
+// instruction is logged nestest-style before it executes.
+fn run(options: &Options) -> Result<(), String> {
+    let raw = std::fs::read(&options.rom_path)
+        .map_err(|e| format!("Cannot read {}: {}", options.rom_path, e))?;
+    let rom = Rom::from_bytes(&raw)?;
+
+    let mut cpu = CPU::new();
+    cpu.bus = Bus::with_rom(rom)?;
+    cpu.reset();
+
+    let stdout = std::io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    let mut halted = false;
+
+    while !halted && cpu.bus.ppu.frame_count() < options.frames {
+        if options.trace {
+            // A closed pipe (e.g. piping into head) just ends the run
+            if writeln!(out, "{}", cpu.trace()).is_err() {
+                return Ok(());
+            }
+            halted = cpu.step().map_err(|e| e.to_string())?;
+        } else {
+            halted = cpu.step_frame().map_err(|e| e.to_string())?;
+        }
+    }
+
+    let _ = writeln!(
+        out,
+        "{} frames, {} cycles{}\n{}",
+        cpu.bus.ppu.frame_count(),
+        cpu.cycles(),
+        if halted { ", CPU halted" } else { "" },
+        cpu
+    );
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = parse_args(&args).and_then(|options| run(&options));
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("{}", message);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_rom_path_and_flags() {
+        assert_eq!(
+            parse_args(&args(&["game.nes", "--trace", "--frames", "5"])),
+            Ok(Options {
+                rom_path: "game.nes".to_string(),
+                trace: true,
+                frames: 5,
+            })
+        );
+        assert_eq!(
+            parse_args(&args(&["game.nes"])).map(|o| (o.trace, o.frames)),
+            Ok((false, DEFAULT_FRAMES))
+        );
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        assert!(parse_args(&args(&[])).is_err());
+        assert!(parse_args(&args(&["a.nes", "b.nes"])).is_err());
+        assert!(parse_args(&args(&["a.nes", "--frames"])).is_err());
+        assert!(parse_args(&args(&["a.nes", "--speed"])).is_err());
+    }
 }
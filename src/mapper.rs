@@ -0,0 +1,593 @@
+#![allow(dead_code)]
+
+use crate::cartridge::{Mirroring, Rom};
+use crate::state::{StateReader, StateWriter};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+const PRG_ROM: u16 = 0x8000;
+const PRG_ROM_BANK_SIZE: usize = 0x4000;
+const CHR_BANK_SIZE: usize = 0x1000;
+const CHR_ROM_PAGE_SIZE: usize = 0x2000;
+
+// Cartridge hardware. The bus hands it CPU addresses in 0x8000-0xFFFF,
+// the PPU hands it pattern table addresses in 0x0000-0x1FFF.
+pub trait Mapper {
+    fn cpu_read(&self, address: u16) -> u8;
+
+    fn cpu_write(&mut self, address: u16, data: u8);
+
+    fn ppu_read(&self, address: u16) -> u8;
+
+    fn ppu_write(&mut self, address: u16, data: u8);
+
+    // How the PPU folds its four nametables into VRAM
+    fn mirroring(&self) -> Mirroring;
+
+    // Level of the cartridge's IRQ line. Boards without an IRQ source never assert it.
+    fn irq(&self) -> bool {
+        false
+    }
+
+    // Bank registers and CHR RAM for save states. Fixed-ROM boards have nothing to save.
+    fn save_state(&self, _state: &mut StateWriter) {}
+
+    fn load_state(&mut self, _state: &mut StateReader) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+// The CPU bus and the PPU both talk to the same cartridge
+pub type SharedMapper = Rc<RefCell<Box<dyn Mapper>>>;
+
+// Picks the board implementation from the iNES mapper number.
+// An empty `chr` means the board carries CHR RAM.
+pub fn create_mapper(
+    number: u8,
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    mirroring: Mirroring,
+) -> Result<Box<dyn Mapper>, String> {
+    mapper_for_rom(Rom::new(prg, chr, number, mirroring))
+}
+
+// Fails on unsupported mapper numbers and on ROM sizes the boards can't bank
+pub fn mapper_for_rom(rom: Rom) -> Result<Box<dyn Mapper>, String> {
+    let prg_len = rom.prg_rom.len();
+    if prg_len == 0 || !prg_len.is_multiple_of(PRG_ROM_BANK_SIZE) {
+        return Err(format!(
+            "PRG ROM must be a non-empty multiple of 16KB, got {} bytes",
+            prg_len
+        ));
+    }
+    let chr_len = rom.chr_rom.len();
+    if chr_len == 0 || !chr_len.is_multiple_of(CHR_ROM_PAGE_SIZE) {
+        return Err(format!(
+            "CHR must be a non-empty multiple of 8KB, got {} bytes",
+            chr_len
+        ));
+    }
+
+    match rom.mapper {
+        0 => Ok(Box::new(Nrom::new(rom))),
+        1 => Ok(Box::new(Mmc1::new(rom))),
+        2 => Ok(Box::new(Uxrom::new(rom))),
+        number => Err(format!("Mapper {} is not supported", number)),
+    }
+}
+
+// Mapper 0: no bank switching. PRG is fixed ROM; CHR is fixed ROM or 8KB of RAM.
+pub struct Nrom {
+    prg_rom: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    mirroring: Mirroring,
+}
+
+impl Nrom {
+    pub fn new(rom: Rom) -> Self {
+        Nrom {
+            prg_rom: rom.prg_rom,
+            chr: rom.chr_rom,
+            chr_is_ram: rom.chr_ram,
+            mirroring: rom.screen_mirroring,
+        }
+    }
+}
+
+impl Mapper for Nrom {
+    // NROM-128 carts have a single 16KB bank, visible at both 0x8000 and 0xC000
+    fn cpu_read(&self, address: u16) -> u8 {
+        let mut offset = (address - PRG_ROM) as usize;
+        if self.prg_rom.len() == PRG_ROM_BANK_SIZE {
+            offset %= PRG_ROM_BANK_SIZE;
+        }
+        self.prg_rom[offset]
+    }
+
+    fn cpu_write(&mut self, _address: u16, _data: u8) {
+        // PRG is ROM, writes are ignored
+    }
+
+    fn ppu_read(&self, address: u16) -> u8 {
+        self.chr[address as usize]
+    }
+
+    // Writes to CHR ROM are ignored
+    fn ppu_write(&mut self, address: u16, data: u8) {
+        if self.chr_is_ram {
+            self.chr[address as usize] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        if self.chr_is_ram {
+            state.bytes(&self.chr);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        if self.chr_is_ram {
+            state.bytes(&mut self.chr)?;
+        }
+        Ok(())
+    }
+}
+
+// Mapper 2: a switchable 16KB bank at 0x8000, with the last bank fixed at 0xC000.
+// Boards without CHR ROM carry 8KB of CHR RAM instead.
+pub struct Uxrom {
+    prg_rom: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    mirroring: Mirroring,
+    bank_select: usize,
+}
+
+impl Uxrom {
+    pub fn new(rom: Rom) -> Self {
+        Uxrom {
+            prg_rom: rom.prg_rom,
+            chr: rom.chr_rom,
+            chr_is_ram: rom.chr_ram,
+            mirroring: rom.screen_mirroring,
+            bank_select: 0,
+        }
+    }
+
+    fn bank_count(&self) -> usize {
+        self.prg_rom.len() / PRG_ROM_BANK_SIZE
+    }
+}
+
+impl Mapper for Uxrom {
+    fn cpu_read(&self, address: u16) -> u8 {
+        let offset = (address - PRG_ROM) as usize % PRG_ROM_BANK_SIZE;
+        let bank = match address {
+            0x8000..=0xBFFF => self.bank_select,
+            _ => self.bank_count() - 1,
+        };
+        self.prg_rom[bank * PRG_ROM_BANK_SIZE + offset]
+    }
+
+    // Any write in cartridge space latches the bank number
+    fn cpu_write(&mut self, _address: u16, data: u8) {
+        self.bank_select = data as usize % self.bank_count();
+    }
+
+    fn ppu_read(&self, address: u16) -> u8 {
+        self.chr[address as usize]
+    }
+
+    fn ppu_write(&mut self, address: u16, data: u8) {
+        if self.chr_is_ram {
+            self.chr[address as usize] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.usize(self.bank_select);
+        if self.chr_is_ram {
+            state.bytes(&self.chr);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.bank_select = state.usize()? % self.bank_count();
+        if self.chr_is_ram {
+            state.bytes(&mut self.chr)?;
+        }
+        Ok(())
+    }
+}
+
+// MMC1 control register bits
+const MMC1_MIRRORING: u8 = 0b0000_0011;
+const MMC1_PRG_MODE: u8 = 0b0000_1100;
+const MMC1_CHR_4K_MODE: u8 = 0b0001_0000;
+const MMC1_SHIFT_RESET: u8 = 0b1000_0000;
+
+// Mapper 1: registers are loaded one bit at a time through a 5-bit shift register.
+// The fifth write commits the value to the register picked by address bits 13-14.
+pub struct Mmc1 {
+    prg_rom: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+
+    shift: u8,
+    shift_count: u8,
+
+    control: u8,
+    chr_bank_0: u8,
+    chr_bank_1: u8,
+    prg_bank: u8,
+}
+
+impl Mmc1 {
+    pub fn new(rom: Rom) -> Self {
+        Mmc1 {
+            prg_rom: rom.prg_rom,
+            chr: rom.chr_rom,
+            chr_is_ram: rom.chr_ram,
+            shift: 0,
+            shift_count: 0,
+            // Power on in PRG mode 3 so the reset vector comes from the last bank
+            control: MMC1_PRG_MODE,
+            chr_bank_0: 0,
+            chr_bank_1: 0,
+            prg_bank: 0,
+        }
+    }
+
+    fn prg_bank_count(&self) -> usize {
+        self.prg_rom.len() / PRG_ROM_BANK_SIZE
+    }
+
+    fn reset_shift(&mut self) {
+        self.shift = 0;
+        self.shift_count = 0;
+    }
+
+    fn commit(&mut self, address: u16, value: u8) {
+        match address {
+            0x8000..=0x9FFF => self.control = value,
+            0xA000..=0xBFFF => self.chr_bank_0 = value,
+            0xC000..=0xDFFF => self.chr_bank_1 = value,
+            _ => self.prg_bank = value & 0x0F,
+        }
+    }
+
+    fn prg_bank_for(&self, address: u16) -> usize {
+        let bank = self.prg_bank as usize;
+        let last = self.prg_bank_count() - 1;
+        let upper = address >= 0xC000;
+
+        let selected = match (self.control & MMC1_PRG_MODE) >> 2 {
+            // 32KB mode ignores the low bit of the bank number
+            0 | 1 => (bank & !1) + upper as usize,
+            // First bank fixed at 0x8000, switch 0xC000
+            2 => {
+                if upper {
+                    bank
+                } else {
+                    0
+                }
+            }
+            // Last bank fixed at 0xC000, switch 0x8000
+            _ => {
+                if upper {
+                    last
+                } else {
+                    bank
+                }
+            }
+        };
+        selected % self.prg_bank_count()
+    }
+
+    fn chr_offset(&self, address: u16) -> usize {
+        let bank_count = self.chr.len() / CHR_BANK_SIZE;
+        let bank = if self.control & MMC1_CHR_4K_MODE != 0 {
+            match address {
+                0x0000..=0x0FFF => self.chr_bank_0 as usize,
+                _ => self.chr_bank_1 as usize,
+            }
+        } else {
+            // 8KB mode ignores the low bit and uses bank 0 for both halves
+            (self.chr_bank_0 as usize & !1) + (address >= 0x1000) as usize
+        };
+        (bank % bank_count) * CHR_BANK_SIZE + (address as usize % CHR_BANK_SIZE)
+    }
+}
+
+impl Mapper for Mmc1 {
+    fn cpu_read(&self, address: u16) -> u8 {
+        let offset = (address - PRG_ROM) as usize % PRG_ROM_BANK_SIZE;
+        self.prg_rom[self.prg_bank_for(address) * PRG_ROM_BANK_SIZE + offset]
+    }
+
+    fn cpu_write(&mut self, address: u16, data: u8) {
+        if data & MMC1_SHIFT_RESET != 0 {
+            self.reset_shift();
+            self.control |= MMC1_PRG_MODE;
+            return;
+        }
+
+        self.shift |= (data & 1) << self.shift_count;
+        self.shift_count += 1;
+
+        if self.shift_count == 5 {
+            let value = self.shift;
+            self.commit(address, value);
+            self.reset_shift();
+        }
+    }
+
+    fn ppu_read(&self, address: u16) -> u8 {
+        self.chr[self.chr_offset(address)]
+    }
+
+    fn ppu_write(&mut self, address: u16, data: u8) {
+        if self.chr_is_ram {
+            let offset = self.chr_offset(address);
+            self.chr[offset] = data;
+        }
+    }
+
+    // The header's mirroring is ignored; MMC1 switches it at runtime
+    fn mirroring(&self) -> Mirroring {
+        match self.control & MMC1_MIRRORING {
+            0 => Mirroring::SingleScreenLower,
+            1 => Mirroring::SingleScreenUpper,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.u8(self.shift);
+        state.u8(self.shift_count);
+        state.u8(self.control);
+        state.u8(self.chr_bank_0);
+        state.u8(self.chr_bank_1);
+        state.u8(self.prg_bank);
+        if self.chr_is_ram {
+            state.bytes(&self.chr);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.shift = state.u8()?;
+        self.shift_count = state.u8()?;
+        self.control = state.u8()?;
+        self.chr_bank_0 = state.u8()?;
+        self.chr_bank_1 = state.u8()?;
+        self.prg_bank = state.u8()?;
+        if self.chr_is_ram {
+            state.bytes(&mut self.chr)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    pub fn test_rom(prg_rom: Vec<u8>, chr_rom: Vec<u8>) -> Rom {
+        Rom::new(prg_rom, chr_rom, 0, Mirroring::Horizontal)
+    }
+
+    #[test]
+    fn create_mapper_dispatches_on_the_mapper_number() {
+        let mut prg = vec![0x11; PRG_ROM_BANK_SIZE];
+        prg.extend(vec![0x22; PRG_ROM_BANK_SIZE]);
+
+        // NROM: fixed banks, and bank-select writes do nothing
+        let mut nrom = create_mapper(0, prg.clone(), vec![0; 0x2000], Mirroring::Vertical).unwrap();
+        nrom.cpu_write(0x8000, 1);
+        assert_eq!(nrom.cpu_read(0x8000), 0x11);
+        assert_eq!(nrom.mirroring(), Mirroring::Vertical);
+
+        // UxROM switches the bank at 0x8000
+        let mut uxrom = create_mapper(2, prg, vec![], Mirroring::Vertical).unwrap();
+        uxrom.cpu_write(0x8000, 1);
+        assert_eq!(uxrom.cpu_read(0x8000), 0x22);
+
+        assert_eq!(
+            create_mapper(4, vec![0; PRG_ROM_BANK_SIZE], vec![], Mirroring::Vertical).err(),
+            Some("Mapper 4 is not supported".to_string())
+        );
+    }
+
+    #[test]
+    fn create_mapper_rejects_unbankable_sizes() {
+        // Empty PRG would index out of bounds on the first NROM read
+        let err = create_mapper(0, vec![], vec![0; 0x2000], Mirroring::Vertical).err();
+        assert!(err.is_some_and(|e| e.contains("PRG ROM")));
+
+        // Less than one bank leaves UxROM with zero banks to select from
+        let err = create_mapper(2, vec![0; 0x100], vec![], Mirroring::Vertical).err();
+        assert!(err.is_some_and(|e| e.contains("PRG ROM")));
+
+        let err = create_mapper(
+            0,
+            vec![0; PRG_ROM_BANK_SIZE],
+            vec![0; 0x100],
+            Mirroring::Vertical,
+        )
+        .err();
+        assert!(err.is_some_and(|e| e.contains("CHR")));
+    }
+
+    #[test]
+    fn nrom_mirrors_single_prg_bank() {
+        let mut prg = vec![0xEA; PRG_ROM_BANK_SIZE];
+        prg[0x3FFC] = 0x00;
+        prg[0x3FFD] = 0xC0;
+        let nrom = Nrom::new(test_rom(prg, vec![0; 0x2000]));
+
+        assert_eq!(nrom.cpu_read(0xFFFC), 0x00);
+        assert_eq!(nrom.cpu_read(0xFFFD), 0xC0);
+        assert_eq!(nrom.cpu_read(0x8000), 0xEA);
+        assert_eq!(nrom.cpu_read(0xC000), 0xEA);
+    }
+
+    #[test]
+    fn nrom_two_prg_banks_are_not_mirrored() {
+        let mut prg = vec![0x11; PRG_ROM_BANK_SIZE];
+        prg.extend(vec![0x22; PRG_ROM_BANK_SIZE]);
+        let nrom = Nrom::new(test_rom(prg, vec![0; 0x2000]));
+
+        assert_eq!(nrom.cpu_read(0x8000), 0x11);
+        assert_eq!(nrom.cpu_read(0xC000), 0x22);
+    }
+
+    #[test]
+    fn nrom_ignores_writes() {
+        let mut chr = vec![0; 0x2000];
+        chr[0x10] = 0x33;
+        let mut nrom = Nrom::new(test_rom(vec![0x5A; PRG_ROM_BANK_SIZE], chr));
+        nrom.cpu_write(0x8000, 0xFF);
+        nrom.ppu_write(0x10, 0xFF);
+
+        assert_eq!(nrom.cpu_read(0x8000), 0x5A);
+        assert_eq!(nrom.ppu_read(0x10), 0x33);
+    }
+
+    // Fills each 16KB bank with its own index so reads show which bank is mapped
+    fn banked_prg(banks: usize) -> Vec<u8> {
+        (0..banks)
+            .flat_map(|bank| vec![bank as u8; PRG_ROM_BANK_SIZE])
+            .collect()
+    }
+
+    #[test]
+    fn uxrom_switches_low_bank_and_fixes_last() {
+        let mut uxrom = Uxrom::new(test_rom(banked_prg(8), vec![]));
+        assert_eq!(uxrom.cpu_read(0x8000), 0);
+        assert_eq!(uxrom.cpu_read(0xC000), 7);
+
+        uxrom.cpu_write(0x8000, 3);
+        assert_eq!(uxrom.cpu_read(0x8000), 3);
+        assert_eq!(uxrom.cpu_read(0xBFFF), 3);
+        assert_eq!(uxrom.cpu_read(0xC000), 7);
+
+        // Bank numbers past the end wrap around to the available banks
+        uxrom.cpu_write(0xFFFF, 10);
+        assert_eq!(uxrom.cpu_read(0x8000), 2);
+        assert_eq!(uxrom.cpu_read(0xFFFF), 7);
+    }
+
+    #[test]
+    fn uxrom_chr_ram_is_writable() {
+        let mut uxrom = Uxrom::new(test_rom(banked_prg(2), vec![]));
+        uxrom.ppu_write(0x1234, 0xAB);
+
+        assert_eq!(uxrom.ppu_read(0x1234), 0xAB);
+    }
+
+    // Clocks a 5-bit value into MMC1, least significant bit first
+    fn mmc1_load(mmc1: &mut Mmc1, address: u16, value: u8) {
+        for bit in 0..5 {
+            mmc1.cpu_write(address, (value >> bit) & 1);
+        }
+    }
+
+    #[test]
+    fn mmc1_serial_load_selects_prg_bank() {
+        let mut mmc1 = Mmc1::new(test_rom(banked_prg(8), vec![]));
+        assert_eq!(mmc1.cpu_read(0x8000), 0);
+        assert_eq!(mmc1.cpu_read(0xC000), 7);
+
+        mmc1_load(&mut mmc1, 0xE000, 5);
+        assert_eq!(mmc1.cpu_read(0x8000), 5);
+        assert_eq!(mmc1.cpu_read(0xC000), 7);
+
+        // Four writes are not enough to commit
+        for _ in 0..4 {
+            mmc1.cpu_write(0xE000, 0);
+        }
+        assert_eq!(mmc1.cpu_read(0x8000), 5);
+    }
+
+    #[test]
+    fn mmc1_prg_modes() {
+        let mut mmc1 = Mmc1::new(test_rom(banked_prg(8), vec![]));
+        mmc1_load(&mut mmc1, 0xE000, 5);
+
+        // Mode 2: first bank fixed at 0x8000, switchable at 0xC000
+        mmc1_load(&mut mmc1, 0x8000, 0b01000);
+        assert_eq!(mmc1.cpu_read(0x8000), 0);
+        assert_eq!(mmc1.cpu_read(0xC000), 5);
+
+        // Mode 0: 32KB switching, low bit of the bank ignored
+        mmc1_load(&mut mmc1, 0x8000, 0b00000);
+        assert_eq!(mmc1.cpu_read(0x8000), 4);
+        assert_eq!(mmc1.cpu_read(0xC000), 5);
+    }
+
+    #[test]
+    fn mmc1_bit_7_resets_shift_register() {
+        let mut mmc1 = Mmc1::new(test_rom(banked_prg(8), vec![]));
+        mmc1_load(&mut mmc1, 0x8000, 0b00000); // 32KB mode
+
+        mmc1.cpu_write(0xE000, 1);
+        mmc1.cpu_write(0xE000, 1);
+        mmc1.cpu_write(0xE000, 0x80); // Reset: drop partial load, back to PRG mode 3
+
+        mmc1_load(&mut mmc1, 0xE000, 2);
+        assert_eq!(mmc1.cpu_read(0x8000), 2);
+        assert_eq!(mmc1.cpu_read(0xC000), 7);
+    }
+
+    #[test]
+    fn mmc1_chr_4k_banks() {
+        let chr: Vec<u8> = (0..4).flat_map(|bank| vec![bank; CHR_BANK_SIZE]).collect();
+        let mut mmc1 = Mmc1::new(test_rom(banked_prg(2), chr));
+
+        mmc1_load(&mut mmc1, 0x8000, 0b11100); // 4KB CHR mode
+        mmc1_load(&mut mmc1, 0xA000, 3);
+        mmc1_load(&mut mmc1, 0xC000, 1);
+
+        assert_eq!(mmc1.ppu_read(0x0000), 3);
+        assert_eq!(mmc1.ppu_read(0x1000), 1);
+    }
+
+    #[test]
+    fn mmc1_control_selects_mirroring() {
+        let mut mmc1 = Mmc1::new(test_rom(banked_prg(2), vec![]));
+        for (bits, mirroring) in [
+            (0, Mirroring::SingleScreenLower),
+            (1, Mirroring::SingleScreenUpper),
+            (2, Mirroring::Vertical),
+            (3, Mirroring::Horizontal),
+        ] {
+            mmc1_load(&mut mmc1, 0x8000, MMC1_PRG_MODE | bits);
+            assert_eq!(mmc1.mirroring(), mirroring);
+        }
+    }
+
+    #[test]
+    fn mmc1_state_round_trips_registers_and_chr_ram() {
+        let mut mmc1 = Mmc1::new(test_rom(banked_prg(8), vec![]));
+        mmc1_load(&mut mmc1, 0xE000, 5);
+        mmc1.ppu_write(0x0010, 0xAB);
+        let mut state = StateWriter::new();
+        mmc1.save_state(&mut state);
+        let data = state.into_bytes();
+
+        let mut restored = Mmc1::new(test_rom(banked_prg(8), vec![]));
+        assert_eq!(restored.load_state(&mut StateReader::new(&data)), Ok(()));
+        assert_eq!(restored.cpu_read(0x8000), 5);
+        assert_eq!(restored.ppu_read(0x0010), 0xAB);
+    }
+}
@@ -1,3 +1,4 @@
+use crate::bus::Access;
 use crate::cpu::AddressingMode;
 use std::collections::HashMap;
 
@@ -12,13 +13,42 @@ pub struct OpCode {
 impl OpCode {
     fn new(code: u8, mnemonic: &'static str, len: u8, cycles: u8, mode: AddressingMode) -> Self {
         OpCode {
-            code: code,
-            mnemonic: mnemonic,
-            len: len,
-            cycles: cycles,
-            mode: mode,
+            code,
+            mnemonic,
+            len,
+            cycles,
+            mode,
         }
     }
+
+    // Whether each cycle of the instruction reads or writes the bus, given the cycles it
+    // actually took. Page-crossing and taken-branch penalties are extra reads, so only the
+    // writes need placing: stores and pushes end on one, read-modify-write ends on a dummy
+    // write and the result, and JSR/BRK push the return address mid-instruction.
+    pub fn access_schedule(&self, cycles: usize) -> Vec<Access> {
+        let writes = match (self.mnemonic, &self.mode) {
+            (_, AddressingMode::Accumulator) => 0..0,
+            ("STA" | "STX" | "STY" | "SAX" | "PHA" | "PHP", _) => cycles.saturating_sub(1)..cycles,
+            (
+                "ASL" | "LSR" | "ROL" | "ROR" | "INC" | "DEC" | "SLO" | "RLA" | "SRE" | "RRA"
+                | "DCP" | "ISB",
+                _,
+            ) => cycles.saturating_sub(2)..cycles,
+            ("JSR", _) => 3..5,
+            ("BRK", _) => 2..5,
+            _ => 0..0,
+        };
+
+        (0..cycles)
+            .map(|cycle| {
+                if writes.contains(&cycle) {
+                    Access::Write
+                } else {
+                    Access::Read
+                }
+            })
+            .collect()
+    }
 }
 /* Copy and Paste
         OpCode::new(0x00, "MNE", 0, 0, AddressingMode::Immediate),
@@ -36,6 +66,70 @@ impl OpCode {
 
 */
 
+// Operand bytes plus the opcode itself, as implied by the addressing mode
+fn expected_len(mode: &AddressingMode) -> u8 {
+    match mode {
+        AddressingMode::Accumulator | AddressingMode::NoneAddressing => 1,
+        AddressingMode::Immediate
+        | AddressingMode::ZeroPage
+        | AddressingMode::ZeroPage_X
+        | AddressingMode::ZeroPage_Y
+        | AddressingMode::Indirect_X
+        | AddressingMode::Indirect_Y
+        | AddressingMode::Relative => 2,
+        AddressingMode::Absolute
+        | AddressingMode::Absolute_X
+        | AddressingMode::Absolute_Y
+        | AddressingMode::Indirect => 3,
+    }
+}
+
+// Catches copy-paste slips: duplicate codes, lengths that disagree with the
+// addressing mode, and cycle counts outside the 2-8 every 6502 opcode falls in
+fn validate_table(ops: &[OpCode]) -> Result<(), String> {
+    let mut seen = [false; 256];
+    for op in ops {
+        if seen[op.code as usize] {
+            return Err(format!(
+                "duplicate opcode {:02X} ({})",
+                op.code, op.mnemonic
+            ));
+        }
+        seen[op.code as usize] = true;
+
+        if op.len != expected_len(&op.mode) {
+            return Err(format!(
+                "{:02X} ({}) has length {}, {:?} needs {}",
+                op.code,
+                op.mnemonic,
+                op.len,
+                op.mode,
+                expected_len(&op.mode)
+            ));
+        }
+        if !(2..=8).contains(&op.cycles) {
+            return Err(format!(
+                "{:02X} ({}) takes an implausible {} cycles",
+                op.code, op.mnemonic, op.cycles
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Panics on an invalid table so a bad entry fails the first test that decodes anything
+fn build_table(ops: &[OpCode]) -> [Option<&OpCode>; 256] {
+    if let Err(e) = validate_table(ops) {
+        panic!("invalid opcode table: {}", e);
+    }
+
+    let mut table = [None; 256];
+    for op in ops {
+        table[op.code as usize] = Some(op);
+    }
+    table
+}
+
 // Opcode Table
 lazy_static! {
     pub static ref CPU_OPS_CODES: Vec<OpCode> = vec![
@@ -60,7 +154,7 @@ lazy_static! {
         OpCode::new(0x31, "AND", 2, 5, AddressingMode::Indirect_Y), // + 1 if page crossed
 
         // Arithmetic Shift Left
-        OpCode::new(0x0A, "ASL", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x0A, "ASL", 1, 2, AddressingMode::Accumulator),
         OpCode::new(0x06, "ASL", 2, 5, AddressingMode::ZeroPage),
         OpCode::new(0x16, "ASL", 2, 6, AddressingMode::ZeroPage_X),
         OpCode::new(0x0E, "ASL", 3, 6, AddressingMode::Absolute),
@@ -70,14 +164,14 @@ lazy_static! {
         Branching
         len +1 if branch succeeds (+2 if to a new page)
         */
-        OpCode::new(0x90, "BCC", 2, 2, AddressingMode::NoneAddressing), // Branch if Carry Clear
-        OpCode::new(0xB0, "BCS", 2, 2, AddressingMode::NoneAddressing), // Branch if Carry Set
-        OpCode::new(0xF0, "BEQ", 2, 2, AddressingMode::NoneAddressing), // Branch if Equal
-        OpCode::new(0x30, "BMI", 2, 2, AddressingMode::NoneAddressing), // Branch if Minus
-        OpCode::new(0xD0, "BNE", 2, 2, AddressingMode::NoneAddressing), // Branch if Not Equal
-        OpCode::new(0x10, "BPL", 2, 2, AddressingMode::NoneAddressing), // Branch if Positive
-        OpCode::new(0x50, "BVC", 2, 2, AddressingMode::NoneAddressing), // Branch if Overflow Clear
-        OpCode::new(0x70, "BVS", 2, 2, AddressingMode::NoneAddressing), // If Overflow set
+        OpCode::new(0x90, "BCC", 2, 2, AddressingMode::Relative), // Branch if Carry Clear
+        OpCode::new(0xB0, "BCS", 2, 2, AddressingMode::Relative), // Branch if Carry Set
+        OpCode::new(0xF0, "BEQ", 2, 2, AddressingMode::Relative), // Branch if Equal
+        OpCode::new(0x30, "BMI", 2, 2, AddressingMode::Relative), // Branch if Minus
+        OpCode::new(0xD0, "BNE", 2, 2, AddressingMode::Relative), // Branch if Not Equal
+        OpCode::new(0x10, "BPL", 2, 2, AddressingMode::Relative), // Branch if Positive
+        OpCode::new(0x50, "BVC", 2, 2, AddressingMode::Relative), // Branch if Overflow Clear
+        OpCode::new(0x70, "BVS", 2, 2, AddressingMode::Relative), // If Overflow set
 
         // Bit Test
         OpCode::new(0x24, "BIT", 2, 3, AddressingMode::ZeroPage),
@@ -139,10 +233,10 @@ lazy_static! {
         OpCode::new(0xC8, "INY", 1, 2, AddressingMode::NoneAddressing),
 
         /* Jumping */
-        OpCode::new(0x4C, "JMP", 0, 0, AddressingMode::NoneAddressing),
-        OpCode::new(0x6C, "JMP", 0, 0, AddressingMode::NoneAddressing),
+        OpCode::new(0x4C, "JMP", 3, 3, AddressingMode::Absolute),
+        OpCode::new(0x6C, "JMP", 3, 5, AddressingMode::Indirect),
 
-        OpCode::new(0x20, "JSR", 3, 6, AddressingMode::NoneAddressing),
+        OpCode::new(0x20, "JSR", 3, 6, AddressingMode::Absolute),
 
 
         /* Load Registers */
@@ -168,7 +262,7 @@ lazy_static! {
         OpCode::new(0xBC, "LDY", 3, 4, AddressingMode::Absolute_X), // +1 if page crossed
 
         // Logical Shift Right
-        OpCode::new(0x4A, "LSR", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x4A, "LSR", 1, 2, AddressingMode::Accumulator),
         OpCode::new(0x46, "LSR", 2, 5, AddressingMode::ZeroPage),
         OpCode::new(0x56, "LSR", 2, 6, AddressingMode::ZeroPage_X),
         OpCode::new(0x4E, "LSR", 3, 6, AddressingMode::Absolute),
@@ -200,13 +294,13 @@ lazy_static! {
         OpCode::new(0x28, "PLP", 1, 4, AddressingMode::NoneAddressing),
 
         /* Rotate Left & Right */
-        OpCode::new(0x2A, "ROL", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x2A, "ROL", 1, 2, AddressingMode::Accumulator),
         OpCode::new(0x26, "ROL", 2, 5, AddressingMode::ZeroPage),
         OpCode::new(0x36, "ROL", 2, 6, AddressingMode::ZeroPage_X),
         OpCode::new(0x2E, "ROL", 3, 6, AddressingMode::Absolute),
         OpCode::new(0x3E, "ROL", 3, 7, AddressingMode::Absolute_X),
 
-        OpCode::new(0x6A, "ROR", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x6A, "ROR", 1, 2, AddressingMode::Accumulator),
         OpCode::new(0x66, "ROR", 2, 5, AddressingMode::ZeroPage),
         OpCode::new(0x76, "ROR", 2, 6, AddressingMode::ZeroPage_X),
         OpCode::new(0x6E, "ROR", 3, 6, AddressingMode::Absolute),
@@ -227,9 +321,9 @@ lazy_static! {
         OpCode::new(0xF1, "SBC", 2, 5, AddressingMode::Indirect_Y), // +1 if page crossed
 
         /* Setting Flags */
-        OpCode::new(0x38, "SEC", 1, 2, AddressingMode::Immediate), // Set Clear
-        OpCode::new(0xF8, "SED", 1, 2, AddressingMode::Immediate), // Set Decimal
-        OpCode::new(0x78, "SEI", 1, 2, AddressingMode::Immediate), // Set Interrupt Disable
+        OpCode::new(0x38, "SEC", 1, 2, AddressingMode::NoneAddressing), // Set Carry
+        OpCode::new(0xF8, "SED", 1, 2, AddressingMode::NoneAddressing), // Set Decimal
+        OpCode::new(0x78, "SEI", 1, 2, AddressingMode::NoneAddressing), // Set Interrupt Disable
 
         /* Store values in registers */
         OpCode::new(0x85, "STA", 2, 3, AddressingMode::ZeroPage),
@@ -242,11 +336,11 @@ lazy_static! {
 
         OpCode::new(0x86, "STX", 2, 3, AddressingMode::ZeroPage),
         OpCode::new(0x96, "STX", 2, 4, AddressingMode::ZeroPage_Y),
-        OpCode::new(0x8E, "STX", 3, 4, AddressingMode::NoneAddressing),
+        OpCode::new(0x8E, "STX", 3, 4, AddressingMode::Absolute),
 
         OpCode::new(0x84, "STY", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x94, "STY", 2, 4, AddressingMode::ZeroPage_Y),
-        OpCode::new(0x8C, "STY", 3, 4, AddressingMode::NoneAddressing),
+        OpCode::new(0x94, "STY", 2, 4, AddressingMode::ZeroPage_X),
+        OpCode::new(0x8C, "STY", 3, 4, AddressingMode::Absolute),
 
         /* Transfers */
         OpCode::new(0xAA, "TAX", 1, 2, AddressingMode::NoneAddressing), // A -> X
@@ -255,6 +349,125 @@ lazy_static! {
         OpCode::new(0x8A, "TXA", 1, 2, AddressingMode::NoneAddressing), // X -> A
         OpCode::new(0x9A, "TXS", 1, 2, AddressingMode::NoneAddressing), // X -> SP
         OpCode::new(0x98, "TYA", 1, 2, AddressingMode::NoneAddressing), // Y -> A
+
+        /* Unofficial opcodes */
+
+        // Load A and X
+        OpCode::new(0xA7, "LAX", 2, 3, AddressingMode::ZeroPage),
+        OpCode::new(0xB7, "LAX", 2, 4, AddressingMode::ZeroPage_Y),
+        OpCode::new(0xAF, "LAX", 3, 4, AddressingMode::Absolute),
+        OpCode::new(0xBF, "LAX", 3, 4, AddressingMode::Absolute_Y), // +1 if page crossed
+        OpCode::new(0xA3, "LAX", 2, 6, AddressingMode::Indirect_X),
+        OpCode::new(0xB3, "LAX", 2, 5, AddressingMode::Indirect_Y), // +1 if page crossed
+
+        // Store A AND X
+        OpCode::new(0x87, "SAX", 2, 3, AddressingMode::ZeroPage),
+        OpCode::new(0x97, "SAX", 2, 4, AddressingMode::ZeroPage_Y),
+        OpCode::new(0x8F, "SAX", 3, 4, AddressingMode::Absolute),
+        OpCode::new(0x83, "SAX", 2, 6, AddressingMode::Indirect_X),
+
+        // Decrement then Compare
+        OpCode::new(0xC7, "DCP", 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0xD7, "DCP", 2, 6, AddressingMode::ZeroPage_X),
+        OpCode::new(0xCF, "DCP", 3, 6, AddressingMode::Absolute),
+        OpCode::new(0xDF, "DCP", 3, 7, AddressingMode::Absolute_X),
+        OpCode::new(0xDB, "DCP", 3, 7, AddressingMode::Absolute_Y),
+        OpCode::new(0xC3, "DCP", 2, 8, AddressingMode::Indirect_X),
+        OpCode::new(0xD3, "DCP", 2, 8, AddressingMode::Indirect_Y),
+
+        // Increment then Subtract with Carry
+        OpCode::new(0xE7, "ISB", 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0xF7, "ISB", 2, 6, AddressingMode::ZeroPage_X),
+        OpCode::new(0xEF, "ISB", 3, 6, AddressingMode::Absolute),
+        OpCode::new(0xFF, "ISB", 3, 7, AddressingMode::Absolute_X),
+        OpCode::new(0xFB, "ISB", 3, 7, AddressingMode::Absolute_Y),
+        OpCode::new(0xE3, "ISB", 2, 8, AddressingMode::Indirect_X),
+        OpCode::new(0xF3, "ISB", 2, 8, AddressingMode::Indirect_Y),
+
+        // Shift Left then OR
+        OpCode::new(0x07, "SLO", 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0x17, "SLO", 2, 6, AddressingMode::ZeroPage_X),
+        OpCode::new(0x0F, "SLO", 3, 6, AddressingMode::Absolute),
+        OpCode::new(0x1F, "SLO", 3, 7, AddressingMode::Absolute_X),
+        OpCode::new(0x1B, "SLO", 3, 7, AddressingMode::Absolute_Y),
+        OpCode::new(0x03, "SLO", 2, 8, AddressingMode::Indirect_X),
+        OpCode::new(0x13, "SLO", 2, 8, AddressingMode::Indirect_Y),
+
+        // Rotate Left then AND
+        OpCode::new(0x27, "RLA", 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0x37, "RLA", 2, 6, AddressingMode::ZeroPage_X),
+        OpCode::new(0x2F, "RLA", 3, 6, AddressingMode::Absolute),
+        OpCode::new(0x3F, "RLA", 3, 7, AddressingMode::Absolute_X),
+        OpCode::new(0x3B, "RLA", 3, 7, AddressingMode::Absolute_Y),
+        OpCode::new(0x23, "RLA", 2, 8, AddressingMode::Indirect_X),
+        OpCode::new(0x33, "RLA", 2, 8, AddressingMode::Indirect_Y),
+
+        // Shift Right then Exclusive OR
+        OpCode::new(0x47, "SRE", 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0x57, "SRE", 2, 6, AddressingMode::ZeroPage_X),
+        OpCode::new(0x4F, "SRE", 3, 6, AddressingMode::Absolute),
+        OpCode::new(0x5F, "SRE", 3, 7, AddressingMode::Absolute_X),
+        OpCode::new(0x5B, "SRE", 3, 7, AddressingMode::Absolute_Y),
+        OpCode::new(0x43, "SRE", 2, 8, AddressingMode::Indirect_X),
+        OpCode::new(0x53, "SRE", 2, 8, AddressingMode::Indirect_Y),
+
+        // Rotate Right then Add with Carry
+        OpCode::new(0x67, "RRA", 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0x77, "RRA", 2, 6, AddressingMode::ZeroPage_X),
+        OpCode::new(0x6F, "RRA", 3, 6, AddressingMode::Absolute),
+        OpCode::new(0x7F, "RRA", 3, 7, AddressingMode::Absolute_X),
+        OpCode::new(0x7B, "RRA", 3, 7, AddressingMode::Absolute_Y),
+        OpCode::new(0x63, "RRA", 2, 8, AddressingMode::Indirect_X),
+        OpCode::new(0x73, "RRA", 2, 8, AddressingMode::Indirect_Y),
+
+        // Immediate AND combos
+        OpCode::new(0x0B, "ANC", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0x2B, "ANC", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0x4B, "ALR", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0x6B, "ARR", 2, 2, AddressingMode::Immediate),
+
+        // Jam: locks up the CPU until reset
+        OpCode::new(0x02, "KIL", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x12, "KIL", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x22, "KIL", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x32, "KIL", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x42, "KIL", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x52, "KIL", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x62, "KIL", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x72, "KIL", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x92, "KIL", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0xB2, "KIL", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0xD2, "KIL", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0xF2, "KIL", 1, 2, AddressingMode::NoneAddressing),
+
+        // No Operation variants. The multi-byte forms read their operand and discard it.
+        OpCode::new(0x1A, "NOP", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x3A, "NOP", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x5A, "NOP", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x7A, "NOP", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0xDA, "NOP", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0xFA, "NOP", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x80, "NOP", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0x82, "NOP", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0x89, "NOP", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0xC2, "NOP", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0xE2, "NOP", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0x04, "NOP", 2, 3, AddressingMode::ZeroPage),
+        OpCode::new(0x44, "NOP", 2, 3, AddressingMode::ZeroPage),
+        OpCode::new(0x64, "NOP", 2, 3, AddressingMode::ZeroPage),
+        OpCode::new(0x14, "NOP", 2, 4, AddressingMode::ZeroPage_X),
+        OpCode::new(0x34, "NOP", 2, 4, AddressingMode::ZeroPage_X),
+        OpCode::new(0x54, "NOP", 2, 4, AddressingMode::ZeroPage_X),
+        OpCode::new(0x74, "NOP", 2, 4, AddressingMode::ZeroPage_X),
+        OpCode::new(0xD4, "NOP", 2, 4, AddressingMode::ZeroPage_X),
+        OpCode::new(0xF4, "NOP", 2, 4, AddressingMode::ZeroPage_X),
+        OpCode::new(0x0C, "NOP", 3, 4, AddressingMode::Absolute),
+        OpCode::new(0x1C, "NOP", 3, 4, AddressingMode::Absolute_X), // +1 if page crossed
+        OpCode::new(0x3C, "NOP", 3, 4, AddressingMode::Absolute_X), // +1 if page crossed
+        OpCode::new(0x5C, "NOP", 3, 4, AddressingMode::Absolute_X), // +1 if page crossed
+        OpCode::new(0x7C, "NOP", 3, 4, AddressingMode::Absolute_X), // +1 if page crossed
+        OpCode::new(0xDC, "NOP", 3, 4, AddressingMode::Absolute_X), // +1 if page crossed
+        OpCode::new(0xFC, "NOP", 3, 4, AddressingMode::Absolute_X), // +1 if page crossed
     ];
 
     pub static ref OPCODES_MAP: HashMap<u8, &'static OpCode> = {
@@ -265,5 +478,65 @@ lazy_static! {
         m
     };
 
+    // Dense lookup indexed directly by the opcode byte, used in the hot loop
+    pub static ref OPCODES_TABLE: [Option<&'static OpCode>; 256] = build_table(&CPU_OPS_CODES);
+
+
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn table_matches_map_for_every_code() {
+        for code in 0..=255u8 {
+            let from_table = OPCODES_TABLE[code as usize].map(|op| op as *const OpCode);
+            let from_map = OPCODES_MAP.get(&code).map(|op| *op as *const OpCode);
+            assert_eq!(from_table, from_map, "mismatch for code {:02X}", code);
+        }
+    }
+
+    #[test]
+    fn table_is_valid() {
+        assert_eq!(validate_table(&CPU_OPS_CODES), Ok(()));
+    }
+
+    #[test]
+    #[should_panic(expected = "duplicate opcode A9")]
+    fn duplicate_code_fails_table_construction() {
+        let ops = vec![
+            OpCode::new(0xA9, "LDA", 2, 2, AddressingMode::Immediate),
+            OpCode::new(0xA9, "LDX", 2, 2, AddressingMode::Immediate),
+        ];
+        build_table(&ops);
+    }
+
+    #[test]
+    fn wrong_length_or_cycles_is_rejected() {
+        let ops = [OpCode::new(0xAD, "LDA", 2, 4, AddressingMode::Absolute)];
+        assert!(validate_table(&ops).is_err());
+
+        let ops = [OpCode::new(0xAD, "LDA", 3, 0, AddressingMode::Absolute)];
+        assert!(validate_table(&ops).is_err());
+    }
 
+    #[test]
+    fn access_schedules_place_the_writes() {
+        use Access::{Read as R, Write as W};
+        let schedule = |code: u8, cycles: usize| {
+            OPCODES_TABLE[code as usize]
+                .unwrap()
+                .access_schedule(cycles)
+        };
+
+        assert_eq!(schedule(0xAD, 4), vec![R, R, R, R]); // LDA $nnnn
+        assert_eq!(schedule(0xBD, 5), vec![R, R, R, R, R]); // LDA $nnnn,X across a page
+        assert_eq!(schedule(0x8D, 4), vec![R, R, R, W]); // STA $nnnn
+        assert_eq!(schedule(0x0A, 2), vec![R, R]); // ASL A
+        assert_eq!(schedule(0xFE, 7), vec![R, R, R, R, R, W, W]); // INC $nnnn,X
+        assert_eq!(schedule(0x48, 3), vec![R, R, W]); // PHA
+        assert_eq!(schedule(0x20, 6), vec![R, R, R, W, W, R]); // JSR
+        assert_eq!(schedule(0x00, 7), vec![R, R, W, W, W, R, R]); // BRK
+    }
 }
@@ -0,0 +1,1214 @@
+#![allow(dead_code)]
+
+use crate::cartridge::Mirroring;
+use crate::frame::Frame;
+use crate::mapper::SharedMapper;
+use crate::palette::SYSTEM_PALETTE;
+use crate::state::{StateReader, StateWriter};
+
+// PPUCTRL (0x2000)
+const CTRL_NAMETABLE: u8 = 0b0000_0011;
+const CTRL_VRAM_INCREMENT: u8 = 0b0000_0100; // 0: add 1 (across), 1: add 32 (down)
+const CTRL_SPRITE_PATTERN: u8 = 0b0000_1000; // 0: 0x0000, 1: 0x1000
+const CTRL_BACKGROUND_PATTERN: u8 = 0b0001_0000; // 0: 0x0000, 1: 0x1000
+const CTRL_SPRITE_SIZE: u8 = 0b0010_0000; // 0: 8x8, 1: 8x16
+const CTRL_GENERATE_NMI: u8 = 0b1000_0000;
+
+// Fields of the loopy v/t registers
+const LOOPY_COARSE_X: u16 = 0x001F;
+const LOOPY_COARSE_Y: u16 = 0x03E0;
+const LOOPY_NAMETABLE: u16 = 0x0C00;
+const LOOPY_FINE_Y: u16 = 0x7000;
+
+// PPUMASK (0x2001)
+const MASK_GRAYSCALE: u8 = 0b0000_0001;
+const MASK_SHOW_BACKGROUND: u8 = 0b0000_1000;
+const MASK_SHOW_SPRITES: u8 = 0b0001_0000;
+const MASK_EMPHASIZE_RED: u8 = 0b0010_0000;
+const MASK_EMPHASIZE_GREEN: u8 = 0b0100_0000;
+const MASK_EMPHASIZE_BLUE: u8 = 0b1000_0000;
+
+// PPUSTATUS (0x2002)
+const STATUS_SPRITE_ZERO_HIT: u8 = 0b0100_0000;
+const STATUS_VBLANK: u8 = 0b1000_0000;
+
+// OAM attribute byte (byte 2 of each sprite)
+const SPRITE_PALETTE: u8 = 0b0000_0011;
+const SPRITE_BEHIND_BACKGROUND: u8 = 0b0010_0000;
+const SPRITE_FLIP_HORIZONTAL: u8 = 0b0100_0000;
+const SPRITE_FLIP_VERTICAL: u8 = 0b1000_0000;
+const SPRITES_PER_SCANLINE: usize = 8;
+
+// PPU address space
+const CHR_END: u16 = 0x1FFF;
+const NAMETABLES: u16 = 0x2000;
+const NAMETABLES_END: u16 = 0x3EFF;
+const PALETTES: u16 = 0x3F00;
+const PALETTES_END: u16 = 0x3FFF;
+const PPU_ADDRESS_MASK: u16 = 0x3FFF;
+
+// A nametable is 32x30 tile indices followed by a 64-byte attribute table
+const NAMETABLE_SIZE: u16 = 0x400;
+const ATTRIBUTE_TABLE: u16 = 0x3C0;
+const TILES_PER_ROW: usize = 32;
+const PATTERN_TABLE_SIZE: u16 = 0x1000;
+const TILE_BYTES: u16 = 16;
+
+const NAMETABLES_MASK: u16 = 0x0FFF;
+
+// NTSC timing: 262 scanlines of 341 dots. Vblank starts after the 240 visible lines.
+const DOTS_PER_SCANLINE: usize = 341;
+const VISIBLE_SCANLINES: u16 = 240;
+const VBLANK_SCANLINE: u16 = 241;
+const PRE_RENDER_SCANLINE: u16 = 261;
+const SCANLINES_PER_FRAME: u16 = 262;
+
+// 2KB inside the console; four-screen carts add another 2KB, kept here for simplicity
+const VRAM_SIZE: usize = 0x1000;
+const PALETTE_SIZE: usize = 32;
+const OAM_SIZE: usize = 256;
+
+// The 2C02 picture processor as seen through its eight CPU-facing registers.
+// Pattern tables are fetched from the cartridge through the shared mapper.
+pub struct Ppu {
+    mapper: Option<SharedMapper>,
+    vram: [u8; VRAM_SIZE],
+    palette_table: [u8; PALETTE_SIZE],
+    oam: [u8; OAM_SIZE],
+
+    ctrl: u8,
+    mask: u8,
+    status: u8,
+    oam_addr: u8,
+    // Loopy's internal registers. `v` is the current VRAM address, `t` the one
+    // PPUCTRL/PPUSCROLL/PPUADDR build up; both lay out scroll as 0yyy NNYY YYYX XXXX
+    // (fine Y, nametable, coarse Y, coarse X). `fine_x` is the 3-bit pixel offset.
+    v: u16,
+    t: u16,
+    fine_x: u8,
+    // PPUSCROLL and PPUADDR take two writes each and share this toggle (loopy's w)
+    write_latch: bool,
+    // PPUDATA reads below the palettes return the previous fetch
+    read_buffer: u8,
+
+    scanline: u16,
+    dot: usize,
+    frame_count: u64,
+    nmi_pending: bool,
+}
+
+impl Default for Ppu {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+impl Ppu {
+    pub fn new(mapper: Option<SharedMapper>) -> Self {
+        Ppu {
+            mapper,
+            vram: [0; VRAM_SIZE],
+            palette_table: [0; PALETTE_SIZE],
+            oam: [0; OAM_SIZE],
+            ctrl: 0,
+            mask: 0,
+            status: 0,
+            oam_addr: 0,
+            v: 0,
+            t: 0,
+            fine_x: 0,
+            write_latch: false,
+            read_buffer: 0,
+            scanline: 0,
+            dot: 0,
+            frame_count: 0,
+            nmi_pending: false,
+        }
+    }
+
+    // `address` is anywhere in 0x2000-0x3FFF; the registers repeat every 8 bytes
+    pub fn read_register(&mut self, address: u16) -> u8 {
+        match address & 0x0007 {
+            2 => self.read_status(),
+            4 => self.oam[self.oam_addr as usize],
+            7 => self.read_data(),
+            // Write-only registers
+            _ => 0,
+        }
+    }
+
+    pub fn write_register(&mut self, address: u16, data: u8) {
+        match address & 0x0007 {
+            0 => self.write_ctrl(data),
+            1 => self.mask = data,
+            2 => {} // PPUSTATUS is read-only
+            3 => self.oam_addr = data,
+            4 => {
+                self.oam[self.oam_addr as usize] = data;
+                self.oam_addr = self.oam_addr.wrapping_add(1);
+            }
+            5 => self.write_scroll(data),
+            6 => self.write_addr(data),
+            7 => self.write_data(data),
+            _ => unreachable!(),
+        }
+    }
+
+    // Same as read_register but without clearing flags or moving the address
+    pub fn peek_register(&self, address: u16) -> u8 {
+        match address & 0x0007 {
+            2 => self.status,
+            4 => self.oam[self.oam_addr as usize],
+            7 => self.read_buffer,
+            _ => 0,
+        }
+    }
+
+    // OAM DMA fills sprite memory starting at OAMADDR, wrapping around
+    pub fn write_oam_dma(&mut self, data: &[u8; OAM_SIZE]) {
+        for &byte in data {
+            self.oam[self.oam_addr as usize] = byte;
+            self.oam_addr = self.oam_addr.wrapping_add(1);
+        }
+    }
+
+    pub fn scanline(&self) -> u16 {
+        self.scanline
+    }
+
+    pub fn dot(&self) -> usize {
+        self.dot
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    // Dots left before vblank next begins, possibly in the following frame
+    pub fn dots_until_vblank(&self) -> usize {
+        self.dots_until_scanline(VBLANK_SCANLINE)
+    }
+
+    // Dots left before the frame wraps back to scanline 0
+    pub fn dots_until_frame_end(&self) -> usize {
+        self.dots_until_scanline(0)
+    }
+
+    fn dots_until_scanline(&self, target: u16) -> usize {
+        let lines = (target + SCANLINES_PER_FRAME - self.scanline - 1) % SCANLINES_PER_FRAME + 1;
+        lines as usize * DOTS_PER_SCANLINE - self.dot
+    }
+
+    pub fn tick(&mut self, dots: usize) {
+        self.dot += dots;
+        loop {
+            self.check_sprite_zero_hit();
+            if self.dot < DOTS_PER_SCANLINE {
+                break;
+            }
+            self.dot -= DOTS_PER_SCANLINE;
+            self.scanline += 1;
+
+            match self.scanline {
+                VBLANK_SCANLINE => {
+                    self.status |= STATUS_VBLANK;
+                    if self.ctrl & CTRL_GENERATE_NMI != 0 {
+                        self.nmi_pending = true;
+                    }
+                }
+                PRE_RENDER_SCANLINE => self.pre_render(),
+                SCANLINES_PER_FRAME => {
+                    self.scanline = 0;
+                    self.frame_count += 1;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Raises sprite-zero hit once the beam has passed the first pixel on this scanline
+    // where sprite 0 and the background are both opaque. Pixel x is output at dot x + 1.
+    fn check_sprite_zero_hit(&mut self) {
+        let rendering = MASK_SHOW_BACKGROUND | MASK_SHOW_SPRITES;
+        if self.scanline >= VISIBLE_SCANLINES
+            || self.mask & rendering != rendering
+            || self.status & STATUS_SPRITE_ZERO_HIT != 0
+        {
+            return;
+        }
+
+        let y = self.scanline as usize;
+        let left = self.oam[3] as usize;
+        // The hit never triggers at x=255
+        let hit = (left..(left + 8).min(Frame::WIDTH - 1))
+            .find(|&x| self.sprite_pixel(0, x, y) != 0 && self.background_pixel(x, y).0 != 0);
+        if hit.is_some_and(|x| self.dot > x) {
+            self.status |= STATUS_SPRITE_ZERO_HIT;
+        }
+    }
+
+    // Returns whether the PPU has raised an NMI and acknowledges it
+    pub fn poll_nmi(&mut self) -> bool {
+        std::mem::take(&mut self.nmi_pending)
+    }
+
+    // Enabling NMI while vblank is already flagged fires one straight away
+    fn write_ctrl(&mut self, data: u8) {
+        let was_enabled = self.ctrl & CTRL_GENERATE_NMI != 0;
+        self.ctrl = data;
+        self.t = (self.t & !LOOPY_NAMETABLE) | ((data & CTRL_NAMETABLE) as u16) << 10;
+        if !was_enabled && data & CTRL_GENERATE_NMI != 0 && self.status & STATUS_VBLANK != 0 {
+            self.nmi_pending = true;
+        }
+    }
+
+    // Reading PPUSTATUS acknowledges vblank and resets the write latch
+    fn read_status(&mut self) -> u8 {
+        let status = self.status;
+        self.status &= !STATUS_VBLANK;
+        self.write_latch = false;
+        status
+    }
+
+    // X first (coarse X into t, the low 3 bits into fine_x), then Y
+    fn write_scroll(&mut self, data: u8) {
+        if self.write_latch {
+            self.t = (self.t & !(LOOPY_FINE_Y | LOOPY_COARSE_Y))
+                | ((data & 0x07) as u16) << 12
+                | ((data >> 3) as u16) << 5;
+        } else {
+            self.t = (self.t & !LOOPY_COARSE_X) | (data >> 3) as u16;
+            self.fine_x = data & 0x07;
+        }
+        self.write_latch = !self.write_latch;
+    }
+
+    // High byte first, then low byte. The second write copies t into v.
+    fn write_addr(&mut self, data: u8) {
+        if self.write_latch {
+            self.t = (self.t & 0xFF00) | data as u16;
+            self.v = self.t;
+        } else {
+            self.t = (((data as u16) << 8) | (self.t & 0x00FF)) & PPU_ADDRESS_MASK;
+        }
+        self.write_latch = !self.write_latch;
+    }
+
+    fn increment_addr(&mut self) {
+        let step = if self.ctrl & CTRL_VRAM_INCREMENT != 0 {
+            32
+        } else {
+            1
+        };
+        self.v = self.v.wrapping_add(step) & PPU_ADDRESS_MASK;
+    }
+
+    fn read_data(&mut self) -> u8 {
+        let addr = self.v;
+        self.increment_addr();
+
+        match addr {
+            0..=NAMETABLES_END => {
+                let result = self.read_buffer;
+                self.read_buffer = self.ppu_read(addr);
+                result
+            }
+            // Palette reads are not delayed
+            _ => self.ppu_read(addr),
+        }
+    }
+
+    fn write_data(&mut self, data: u8) {
+        self.ppu_write(self.v, data);
+        self.increment_addr();
+    }
+
+    fn ppu_read(&self, address: u16) -> u8 {
+        match address {
+            0..=CHR_END => match &self.mapper {
+                Some(mapper) => mapper.borrow().ppu_read(address),
+                None => 0,
+            },
+            NAMETABLES..=NAMETABLES_END => self.vram[self.mirror_vram_addr(address)],
+            PALETTES..=PALETTES_END => self.palette_table[mirror_palette_addr(address)],
+            _ => unreachable!("PPU address {:04X} out of range", address),
+        }
+    }
+
+    fn ppu_write(&mut self, address: u16, data: u8) {
+        match address {
+            0..=CHR_END => {
+                if let Some(mapper) = &self.mapper {
+                    mapper.borrow_mut().ppu_write(address, data);
+                }
+            }
+            NAMETABLES..=NAMETABLES_END => self.vram[self.mirror_vram_addr(address)] = data,
+            PALETTES..=PALETTES_END => self.palette_table[mirror_palette_addr(address)] = data,
+            _ => unreachable!("PPU address {:04X} out of range", address),
+        }
+    }
+
+    // Draws the background into `frame` at the scroll position held in t and fine X
+    pub fn render_background(&self, frame: &mut Frame) {
+        for y in 0..Frame::HEIGHT {
+            for x in 0..Frame::WIDTH {
+                let (value, palette) = self.background_pixel(x, y);
+                frame.set_pixel(x, y, self.background_color(value, palette));
+            }
+        }
+    }
+
+    // Draws a full picture: the background, then the sprites over it
+    pub fn render(&self, frame: &mut Frame) {
+        self.render_background(frame);
+        self.render_sprites(frame);
+    }
+
+    // The pre-render scanline clears the flags set during the previous frame
+    pub fn pre_render(&mut self) {
+        self.status &= !(STATUS_VBLANK | STATUS_SPRITE_ZERO_HIT);
+    }
+
+    // Draws OAM sprites over an already rendered background. Lower OAM indices win
+    // overlaps, and only the first 8 sprites on each scanline are drawn.
+    // Sprite-zero hit is raised by tick, not here.
+    pub fn render_sprites(&self, frame: &mut Frame) {
+        for y in 0..Frame::HEIGHT {
+            let sprites = self.sprites_on_scanline(y);
+
+            for x in 0..Frame::WIDTH {
+                for &sprite in &sprites {
+                    let value = self.sprite_pixel(sprite, x, y);
+                    if value == 0 {
+                        continue;
+                    }
+
+                    let background_opaque = self.background_pixel(x, y).0 != 0;
+
+                    // A behind-background sprite still hides the sprites after it
+                    let attributes = self.oam[sprite * 4 + 2];
+                    if attributes & SPRITE_BEHIND_BACKGROUND == 0 || !background_opaque {
+                        let palette = attributes & SPRITE_PALETTE;
+                        frame.set_pixel(x, y, self.sprite_color(value, palette));
+                    }
+                    break;
+                }
+            }
+        }
+    }
+
+    // Scroll origin in the 512x480 plane of four nametables, taken from t
+    // the way the PPU reloads v from it at the start of each frame
+    fn scroll_origin(&self) -> (usize, usize) {
+        let coarse_x = (self.t & LOOPY_COARSE_X) as usize;
+        let coarse_y = ((self.t & LOOPY_COARSE_Y) >> 5) as usize;
+        let nametable = ((self.t & LOOPY_NAMETABLE) >> 10) as usize;
+        let fine_y = ((self.t & LOOPY_FINE_Y) >> 12) as usize;
+
+        let x = (nametable & 1) * Frame::WIDTH + coarse_x * 8 + self.fine_x as usize;
+        let y = (nametable >> 1) * Frame::HEIGHT + coarse_y * 8 + fine_y;
+        (x, y)
+    }
+
+    // Pattern value (0-3) and attribute palette of the background at screen pixel (x, y),
+    // after scrolling. Crossing a nametable edge moves on to the neighbouring one.
+    fn background_pixel(&self, x: usize, y: usize) -> (u8, u8) {
+        let (origin_x, origin_y) = self.scroll_origin();
+        let x = (origin_x + x) % (Frame::WIDTH * 2);
+        let y = (origin_y + y) % (Frame::HEIGHT * 2);
+
+        let index = (y / Frame::HEIGHT * 2 + x / Frame::WIDTH) as u16;
+        let nametable = NAMETABLES + index * NAMETABLE_SIZE;
+        let (x, y) = (x % Frame::WIDTH, y % Frame::HEIGHT);
+        let bank = if self.ctrl & CTRL_BACKGROUND_PATTERN != 0 {
+            PATTERN_TABLE_SIZE
+        } else {
+            0
+        };
+
+        let (column, row) = (x / 8, y / 8);
+        let tile = self.ppu_read(nametable + (row * TILES_PER_ROW + column) as u16);
+        let value = self.pattern_value(bank, tile, x % 8, y % 8);
+
+        (value, self.attribute_palette(nametable, column, row))
+    }
+
+    // Each attribute byte covers a 4x4 tile area, two bits per 2x2 quadrant
+    fn attribute_palette(&self, nametable: u16, column: usize, row: usize) -> u8 {
+        let attr_index = (row / 4 * 8 + column / 4) as u16;
+        let attr_byte = self.ppu_read(nametable + ATTRIBUTE_TABLE + attr_index);
+
+        let shift = ((row % 4) / 2 * 2 + (column % 4) / 2) * 2;
+        (attr_byte >> shift) & 0b11
+    }
+
+    fn sprite_height(&self) -> usize {
+        if self.ctrl & CTRL_SPRITE_SIZE != 0 {
+            16
+        } else {
+            8
+        }
+    }
+
+    // OAM indices of the sprites covering scanline `y`, capped at the hardware limit
+    fn sprites_on_scanline(&self, y: usize) -> Vec<usize> {
+        let height = self.sprite_height();
+        (0..OAM_SIZE / 4)
+            .filter(|&sprite| {
+                let top = self.oam[sprite * 4] as usize + 1;
+                (top..top + height).contains(&y)
+            })
+            .take(SPRITES_PER_SCANLINE)
+            .collect()
+    }
+
+    // Pattern value of `sprite` at screen pixel (x, y), 0 where it is transparent or absent
+    fn sprite_pixel(&self, sprite: usize, x: usize, y: usize) -> u8 {
+        let top = self.oam[sprite * 4] as usize + 1;
+        let tile = self.oam[sprite * 4 + 1];
+        let attributes = self.oam[sprite * 4 + 2];
+        let left = self.oam[sprite * 4 + 3] as usize;
+        let height = self.sprite_height();
+
+        if !(left..left + 8).contains(&x) || !(top..top + height).contains(&y) {
+            return 0;
+        }
+
+        let mut column = x - left;
+        let mut row = y - top;
+        if attributes & SPRITE_FLIP_HORIZONTAL != 0 {
+            column = 7 - column;
+        }
+        // Flipping an 8x16 sprite also swaps its two tiles
+        if attributes & SPRITE_FLIP_VERTICAL != 0 {
+            row = height - 1 - row;
+        }
+
+        if height == 16 {
+            // Bit 0 of the index picks the pattern table, the rest the top tile;
+            // the bottom half is the tile after it
+            let bank = (tile & 1) as u16 * PATTERN_TABLE_SIZE;
+            let tile = (tile & 0xFE) + (row / 8) as u8;
+            return self.pattern_value(bank, tile, column, row % 8);
+        }
+
+        let bank = if self.ctrl & CTRL_SPRITE_PATTERN != 0 {
+            PATTERN_TABLE_SIZE
+        } else {
+            0
+        };
+        self.pattern_value(bank, tile, column, row)
+    }
+
+    // A tile is two 8-byte bit planes; bit 7 of each row is the leftmost pixel
+    fn pattern_value(&self, bank: u16, tile: u8, column: usize, row: usize) -> u8 {
+        let addr = bank + tile as u16 * TILE_BYTES + row as u16;
+        let lo = self.ppu_read(addr);
+        let hi = self.ppu_read(addr + 8);
+        let bit = 7 - column;
+
+        (((hi >> bit) & 1) << 1) | ((lo >> bit) & 1)
+    }
+
+    // Colour 0 of every background palette is the shared backdrop at 0x3F00
+    fn background_color(&self, value: u8, palette: u8) -> (u8, u8, u8) {
+        if value == 0 {
+            return self.palette_color(0);
+        }
+        self.palette_color((palette * 4 + value) as usize)
+    }
+
+    // Sprite palettes live at 0x3F10-0x3F1F
+    fn sprite_color(&self, value: u8, palette: u8) -> (u8, u8, u8) {
+        self.palette_color(0x10 + (palette * 4 + value) as usize)
+    }
+
+    // Grayscale keeps only the brightness column of the colour index. Emphasis
+    // darkens the channels that are not emphasized.
+    fn palette_color(&self, index: usize) -> (u8, u8, u8) {
+        let mut color_index = self.palette_table[index] & 0x3F;
+        if self.mask & MASK_GRAYSCALE != 0 {
+            color_index &= 0x30;
+        }
+
+        let (r, g, b) = SYSTEM_PALETTE[color_index as usize];
+        let emphasis =
+            self.mask & (MASK_EMPHASIZE_RED | MASK_EMPHASIZE_GREEN | MASK_EMPHASIZE_BLUE);
+        if emphasis == 0 {
+            return (r, g, b);
+        }
+
+        let dim = |channel: u8, bit: u8| {
+            if emphasis & bit != 0 {
+                channel
+            } else {
+                (channel as u16 * 3 / 4) as u8
+            }
+        };
+        (
+            dim(r, MASK_EMPHASIZE_RED),
+            dim(g, MASK_EMPHASIZE_GREEN),
+            dim(b, MASK_EMPHASIZE_BLUE),
+        )
+    }
+
+    // Folds the four logical nametables (and their 0x3000-0x3EFF mirror) into VRAM.
+    // Without a cartridge the nametables are mirrored vertically.
+    fn mirror_vram_addr(&self, address: u16) -> usize {
+        let mirroring = match &self.mapper {
+            Some(mapper) => mapper.borrow().mirroring(),
+            None => Mirroring::Vertical,
+        };
+
+        let index = (address - NAMETABLES) & NAMETABLES_MASK;
+        let table = index / NAMETABLE_SIZE;
+        let offset = index % NAMETABLE_SIZE;
+
+        let physical = match mirroring {
+            Mirroring::Vertical => table % 2,
+            Mirroring::Horizontal => table / 2,
+            Mirroring::SingleScreenLower => 0,
+            Mirroring::SingleScreenUpper => 1,
+            Mirroring::FourScreen => table,
+        };
+        (physical * NAMETABLE_SIZE + offset) as usize
+    }
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&self.vram);
+        state.bytes(&self.palette_table);
+        state.bytes(&self.oam);
+        state.u8(self.ctrl);
+        state.u8(self.mask);
+        state.u8(self.status);
+        state.u8(self.oam_addr);
+        state.u16(self.v);
+        state.u16(self.t);
+        state.u8(self.fine_x);
+        state.bool(self.write_latch);
+        state.u8(self.read_buffer);
+        state.u16(self.scanline);
+        state.usize(self.dot);
+        state.u64(self.frame_count);
+        state.bool(self.nmi_pending);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        state.bytes(&mut self.vram)?;
+        state.bytes(&mut self.palette_table)?;
+        state.bytes(&mut self.oam)?;
+        self.ctrl = state.u8()?;
+        self.mask = state.u8()?;
+        self.status = state.u8()?;
+        self.oam_addr = state.u8()?;
+        self.v = state.u16()?;
+        self.t = state.u16()?;
+        self.fine_x = state.u8()?;
+        self.write_latch = state.bool()?;
+        self.read_buffer = state.u8()?;
+        self.scanline = state.u16()?;
+        self.dot = state.usize()?;
+        self.frame_count = state.u64()?;
+        self.nmi_pending = state.bool()?;
+        Ok(())
+    }
+}
+
+// 0x3F10/0x3F14/0x3F18/0x3F1C are the backdrop entries of the sprite palettes,
+// which the hardware does not store separately from 0x3F00/0x3F04/0x3F08/0x3F0C
+fn mirror_palette_addr(address: u16) -> usize {
+    let index = (address as usize) % PALETTE_SIZE;
+    if index >= 0x10 && index.is_multiple_of(4) {
+        index - 0x10
+    } else {
+        index
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::cartridge::Rom;
+    use crate::cpu::{CPU, Mem};
+    use crate::mapper::test::test_rom;
+    use crate::mapper::{Mapper, Nrom};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn ppu_with_chr(chr: Vec<u8>) -> Ppu {
+        let mapper: Box<dyn Mapper> = Box::new(Nrom::new(test_rom(vec![0; 0x4000], chr)));
+        Ppu::new(Some(Rc::new(RefCell::new(mapper))))
+    }
+
+    fn ppu_with_mirroring(mirroring: Mirroring) -> Ppu {
+        let mut rom = test_rom(vec![0; 0x4000], vec![0; 0x2000]);
+        rom.screen_mirroring = mirroring;
+        let mapper: Box<dyn Mapper> = Box::new(Nrom::new(rom));
+        Ppu::new(Some(Rc::new(RefCell::new(mapper))))
+    }
+
+    fn write_vram(ppu: &mut Ppu, addr: u16, data: u8) {
+        set_addr(ppu, addr);
+        ppu.write_register(0x2007, data);
+    }
+
+    fn read_vram(ppu: &mut Ppu, addr: u16) -> u8 {
+        set_addr(ppu, addr);
+        ppu.read_register(0x2007);
+        ppu.read_register(0x2007)
+    }
+
+    fn set_addr(ppu: &mut Ppu, addr: u16) {
+        ppu.write_register(0x2006, (addr >> 8) as u8);
+        ppu.write_register(0x2006, (addr & 0xFF) as u8);
+    }
+
+    #[test]
+    fn ppuaddr_takes_high_byte_then_low_byte() {
+        let mut ppu = Ppu::default();
+        set_addr(&mut ppu, 0x2305);
+
+        assert_eq!(ppu.v, 0x2305);
+        assert!(!ppu.write_latch);
+    }
+
+    #[test]
+    fn ppuaddr_is_mirrored_down_to_14_bits() {
+        let mut ppu = Ppu::default();
+        set_addr(&mut ppu, 0x7F05);
+
+        assert_eq!(ppu.v, 0x3F05);
+    }
+
+    #[test]
+    fn reading_status_clears_vblank_and_resets_the_latch() {
+        let mut ppu = Ppu::default();
+        ppu.status |= STATUS_VBLANK;
+        ppu.write_register(0x2006, 0x21);
+
+        assert_eq!(ppu.read_register(0x2002) & STATUS_VBLANK, STATUS_VBLANK);
+        assert_eq!(ppu.read_register(0x2002) & STATUS_VBLANK, 0);
+
+        // The next PPUADDR write is treated as the high byte again
+        set_addr(&mut ppu, 0x2400);
+        assert_eq!(ppu.v, 0x2400);
+    }
+
+    #[test]
+    fn ppudata_increments_by_1_or_32() {
+        let mut ppu = Ppu::default();
+        set_addr(&mut ppu, 0x2000);
+        ppu.write_register(0x2007, 0x11);
+        assert_eq!(ppu.v, 0x2001);
+
+        ppu.write_register(0x2000, CTRL_VRAM_INCREMENT);
+        ppu.write_register(0x2007, 0x22);
+        assert_eq!(ppu.v, 0x2021);
+
+        assert_eq!(ppu.vram[0x000], 0x11);
+        assert_eq!(ppu.vram[0x001], 0x22);
+    }
+
+    #[test]
+    fn ppudata_reads_are_delayed_by_one() {
+        let mut ppu = Ppu::default();
+        ppu.vram[0x0305] = 0x66;
+        ppu.vram[0x0306] = 0x77;
+        set_addr(&mut ppu, 0x2305);
+
+        ppu.read_register(0x2007); // primes the buffer
+        assert_eq!(ppu.read_register(0x2007), 0x66);
+        assert_eq!(ppu.read_register(0x2007), 0x77);
+    }
+
+    #[test]
+    fn palette_reads_are_not_delayed() {
+        let mut ppu = Ppu::default();
+        ppu.palette_table[0x01] = 0x2A;
+        set_addr(&mut ppu, 0x3F01);
+
+        assert_eq!(ppu.read_register(0x2007), 0x2A);
+    }
+
+    #[test]
+    fn sprite_backdrop_entries_mirror_the_background_ones() {
+        let mut ppu = Ppu::default();
+        write_vram(&mut ppu, 0x3F10, 0x21);
+        write_vram(&mut ppu, 0x3F04, 0x16);
+        write_vram(&mut ppu, 0x3F3C, 0x2A); // 0x3F20-0x3FFF repeat the 32 entries
+
+        // Palette reads are not buffered, so one PPUDATA read returns the entry
+        let mut read_palette = |addr| {
+            set_addr(&mut ppu, addr);
+            ppu.read_register(0x2007)
+        };
+        assert_eq!(read_palette(0x3F00), 0x21);
+        assert_eq!(read_palette(0x3F14), 0x16);
+        assert_eq!(read_palette(0x3F0C), 0x2A);
+
+        // Other sprite entries are separate
+        write_vram(&mut ppu, 0x3F11, 0x30);
+        assert_eq!(ppu.palette_table[0x01], 0x00);
+        assert_eq!(ppu.palette_table[0x11], 0x30);
+    }
+
+    #[test]
+    fn grayscale_collapses_colours_to_the_gray_column() {
+        let mut ppu = Ppu::default();
+        let mut frame = Frame::new();
+        for (color, gray) in [(0x16, 0x10), (0x2A, 0x20), (0x0D, 0x00), (0x3C, 0x30)] {
+            ppu.palette_table[0] = color;
+            ppu.write_register(0x2001, MASK_GRAYSCALE);
+            ppu.render_background(&mut frame);
+            assert_eq!(frame.get_pixel(0, 0), SYSTEM_PALETTE[gray]);
+        }
+    }
+
+    #[test]
+    fn emphasis_dims_the_other_channels() {
+        let mut ppu = Ppu::default();
+        ppu.palette_table[0] = 0x30; // white
+        let mut frame = Frame::new();
+
+        ppu.write_register(0x2001, MASK_EMPHASIZE_RED);
+        ppu.render_background(&mut frame);
+        assert_eq!(frame.get_pixel(0, 0), (0xFF, 0xBF, 0xBF));
+
+        ppu.write_register(0x2001, MASK_EMPHASIZE_GREEN | MASK_EMPHASIZE_BLUE);
+        ppu.render_background(&mut frame);
+        assert_eq!(frame.get_pixel(0, 0), (0xBF, 0xFF, 0xFF));
+    }
+
+    #[test]
+    fn oamdata_writes_advance_oamaddr() {
+        let mut ppu = Ppu::default();
+        ppu.write_register(0x2003, 0x10);
+        ppu.write_register(0x2004, 0xAA);
+        ppu.write_register(0x2004, 0xBB);
+
+        assert_eq!(ppu.oam[0x10], 0xAA);
+        assert_eq!(ppu.oam[0x11], 0xBB);
+        assert_eq!(ppu.oam_addr, 0x12);
+    }
+
+    #[test]
+    fn chr_ram_is_writable_through_ppudata_but_chr_rom_is_not() {
+        // One PRG bank and zero CHR banks: the cartridge carries CHR RAM
+        let mut raw = vec![
+            0x4E, 0x45, 0x53, 0x1A, 0x01, 0x00, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        raw.extend(vec![0; 0x4000]);
+        let mapper = crate::mapper::mapper_for_rom(Rom::from_bytes(&raw).unwrap()).unwrap();
+        let mut ppu = Ppu::new(Some(Rc::new(RefCell::new(mapper))));
+
+        write_vram(&mut ppu, 0x0010, 0xA5);
+        write_vram(&mut ppu, 0x1FFF, 0x3C);
+        assert_eq!(read_vram(&mut ppu, 0x0010), 0xA5);
+        assert_eq!(read_vram(&mut ppu, 0x1FFF), 0x3C);
+
+        let mut chr = vec![0; 0x2000];
+        chr[0x10] = 0x11;
+        let mut ppu = ppu_with_chr(chr);
+        write_vram(&mut ppu, 0x0010, 0xA5);
+        assert_eq!(read_vram(&mut ppu, 0x0010), 0x11);
+    }
+
+    #[test]
+    fn render_background_draws_tiles_with_attribute_palettes() {
+        let mut chr = vec![0; 0x2000];
+        chr[0x10] = 0b1000_0000; // tile 1: leftmost pixel of the top row is colour 1
+        chr[0x20..0x30].fill(0xFF); // tile 2: solid colour 3
+        let mut ppu = ppu_with_chr(chr);
+
+        ppu.vram[1] = 1; // tile (1, 0)
+        ppu.vram[2 * 32 + 2] = 2; // tile (2, 2), bottom-right quadrant
+        ppu.vram[0x3C0] = 0b10_00_00_00; // bottom-right quadrant uses palette 2
+        ppu.palette_table[0] = 0x0F;
+        ppu.palette_table[1] = 0x30;
+        ppu.palette_table[11] = 0x16;
+
+        let mut frame = Frame::new();
+        ppu.render_background(&mut frame);
+
+        assert_eq!(frame.get_pixel(8, 0), SYSTEM_PALETTE[0x30]);
+        assert_eq!(frame.get_pixel(9, 0), SYSTEM_PALETTE[0x0F]);
+        assert_eq!(frame.get_pixel(8, 1), SYSTEM_PALETTE[0x0F]);
+        assert_eq!(frame.get_pixel(16, 16), SYSTEM_PALETTE[0x16]);
+        assert_eq!(frame.get_pixel(23, 23), SYSTEM_PALETTE[0x16]);
+        assert_eq!(frame.get_pixel(24, 16), SYSTEM_PALETTE[0x0F]);
+    }
+
+    #[test]
+    fn render_background_uses_pattern_table_from_ppuctrl() {
+        let mut chr = vec![0; 0x2000];
+        chr[0x1000] = 0b1000_0000; // tile 0 of the right pattern table
+        let mut ppu = ppu_with_chr(chr);
+        ppu.palette_table[1] = 0x30;
+
+        let mut frame = Frame::new();
+        ppu.render_background(&mut frame);
+        assert_eq!(frame.get_pixel(0, 0), SYSTEM_PALETTE[0x00]);
+
+        ppu.write_register(0x2000, CTRL_BACKGROUND_PATTERN);
+        ppu.render_background(&mut frame);
+        assert_eq!(frame.get_pixel(0, 0), SYSTEM_PALETTE[0x30]);
+    }
+
+    #[test]
+    fn fine_x_scroll_shifts_the_background_left() {
+        let mut chr = vec![0; 0x2000];
+        chr[0x10] = 0b1000_0000; // tile 1: leftmost pixel of the top row
+        let mut ppu = ppu_with_chr(chr);
+        ppu.vram[1] = 1;
+        ppu.palette_table[1] = 0x30;
+
+        let mut frame = Frame::new();
+        ppu.render_background(&mut frame);
+        assert_eq!(frame.get_pixel(8, 0), SYSTEM_PALETTE[0x30]);
+
+        ppu.write_register(0x2005, 3);
+        ppu.write_register(0x2005, 0);
+        ppu.render_background(&mut frame);
+        assert_eq!(frame.get_pixel(5, 0), SYSTEM_PALETTE[0x30]);
+        assert_eq!(frame.get_pixel(8, 0), SYSTEM_PALETTE[0x00]);
+    }
+
+    #[test]
+    fn scrolling_past_the_edge_reaches_the_next_nametable() {
+        let mut chr = vec![0; 0x2000];
+        chr[0x10..0x18].fill(0xFF); // tile 1: solid colour 1
+        let mut rom = test_rom(vec![0; 0x4000], chr);
+        rom.screen_mirroring = Mirroring::Vertical;
+        let mapper: Box<dyn Mapper> = Box::new(Nrom::new(rom));
+        let mut ppu = Ppu::new(Some(Rc::new(RefCell::new(mapper))));
+        ppu.vram[0x400] = 1; // top-left tile of the nametable at $2400
+        ppu.palette_table[1] = 0x30;
+
+        // Coarse X 31, fine X 7: the screen starts one pixel before $2400
+        ppu.write_register(0x2005, 0xFF);
+        ppu.write_register(0x2005, 0);
+        assert_eq!(ppu.t & LOOPY_COARSE_X, 31);
+        assert_eq!(ppu.fine_x, 7);
+
+        let mut frame = Frame::new();
+        ppu.render_background(&mut frame);
+        assert_eq!(frame.get_pixel(0, 0), SYSTEM_PALETTE[0x00]);
+        assert_eq!(frame.get_pixel(1, 0), SYSTEM_PALETTE[0x30]);
+        assert_eq!(frame.get_pixel(8, 7), SYSTEM_PALETTE[0x30]);
+        assert_eq!(frame.get_pixel(9, 0), SYSTEM_PALETTE[0x00]);
+    }
+
+    #[test]
+    fn ppuctrl_and_ppuaddr_update_t_and_v() {
+        let mut ppu = Ppu::default();
+        ppu.write_register(0x2000, 0b10);
+        assert_eq!(ppu.t & LOOPY_NAMETABLE, 0b10 << 10);
+
+        // PPUSCROLL Y lands in fine Y and coarse Y without touching v
+        ppu.write_register(0x2005, 0);
+        ppu.write_register(0x2005, 0x2D);
+        assert_eq!(ppu.t, 0x58A0); // fine Y 5, nametable 2, coarse Y 5
+        assert_eq!(ppu.v, 0);
+
+        set_addr(&mut ppu, 0x2345);
+        assert_eq!(ppu.t, 0x2345);
+        assert_eq!(ppu.v, 0x2345);
+    }
+
+    fn set_sprite(ppu: &mut Ppu, sprite: usize, x: u8, y: u8, tile: u8, attributes: u8) {
+        ppu.oam[sprite * 4..sprite * 4 + 4].copy_from_slice(&[y, tile, attributes, x]);
+    }
+
+    #[test]
+    fn render_sprites_applies_flips() {
+        let mut chr = vec![0; 0x2000];
+        chr[0x10] = 0b1000_0000; // tile 1: only the top-left pixel is set
+        let mut ppu = ppu_with_chr(chr);
+        ppu.palette_table[0x11] = 0x30;
+        set_sprite(&mut ppu, 0, 16, 9, 1, 0);
+        set_sprite(&mut ppu, 1, 32, 9, 1, SPRITE_FLIP_HORIZONTAL);
+        set_sprite(
+            &mut ppu,
+            2,
+            48,
+            9,
+            1,
+            SPRITE_FLIP_HORIZONTAL | SPRITE_FLIP_VERTICAL,
+        );
+
+        let mut frame = Frame::new();
+        ppu.render_sprites(&mut frame);
+
+        // Sprites are drawn one scanline below their OAM Y
+        assert_eq!(frame.get_pixel(16, 10), SYSTEM_PALETTE[0x30]);
+        assert_eq!(frame.get_pixel(32, 10), (0, 0, 0));
+        assert_eq!(frame.get_pixel(39, 10), SYSTEM_PALETTE[0x30]);
+        assert_eq!(frame.get_pixel(55, 10), (0, 0, 0));
+        assert_eq!(frame.get_pixel(55, 17), SYSTEM_PALETTE[0x30]);
+    }
+
+    #[test]
+    fn tall_sprites_stack_two_tiles_from_the_odd_bit_table() {
+        let mut chr = vec![0; 0x2000];
+        chr[0x1020] = 0b1000_0000; // tile 2 of the right table: top-left pixel
+        chr[0x1038] = 0b0000_0001; // tile 3: top-right pixel, in the high plane
+        let mut ppu = ppu_with_chr(chr);
+        ppu.palette_table[0x11] = 0x30;
+        ppu.palette_table[0x12] = 0x16;
+        ppu.write_register(0x2000, CTRL_SPRITE_SIZE);
+        set_sprite(&mut ppu, 0, 16, 9, 0x03, 0); // index 3: tiles 2 and 3 at 0x1000
+        set_sprite(&mut ppu, 1, 32, 9, 0x03, SPRITE_FLIP_VERTICAL);
+
+        let mut frame = Frame::new();
+        ppu.render_sprites(&mut frame);
+
+        assert_eq!(frame.get_pixel(16, 10), SYSTEM_PALETTE[0x30]);
+        assert_eq!(frame.get_pixel(23, 18), SYSTEM_PALETTE[0x16]);
+        // Flipped: the bottom tile's top row becomes the upper half's last row
+        assert_eq!(frame.get_pixel(39, 17), SYSTEM_PALETTE[0x16]);
+        assert_eq!(frame.get_pixel(32, 25), SYSTEM_PALETTE[0x30]);
+        assert_eq!(frame.get_pixel(32, 10), (0, 0, 0));
+    }
+
+    #[test]
+    fn behind_background_sprites_only_show_through_transparent_background() {
+        let mut chr = vec![0; 0x2000];
+        chr[0x10..0x18].fill(0xFF); // tile 1: solid colour 1
+        let mut ppu = ppu_with_chr(chr);
+        ppu.vram[32] = 1; // opaque background at tile (0, 1), transparent at (1, 1)
+        ppu.palette_table[0x01] = 0x16;
+        ppu.palette_table[0x15] = 0x2A;
+        set_sprite(&mut ppu, 0, 4, 7, 1, SPRITE_BEHIND_BACKGROUND | 1);
+        set_sprite(&mut ppu, 1, 4, 7, 1, 0);
+
+        let mut frame = Frame::new();
+        ppu.render_background(&mut frame);
+        ppu.render_sprites(&mut frame);
+
+        // Sprite 0 wins the overlap but is hidden behind the opaque background
+        assert_eq!(frame.get_pixel(4, 8), SYSTEM_PALETTE[0x16]);
+        assert_eq!(frame.get_pixel(8, 8), SYSTEM_PALETTE[0x2A]);
+    }
+
+    #[test]
+    fn only_eight_sprites_are_drawn_per_scanline() {
+        let mut chr = vec![0; 0x2000];
+        chr[0x10..0x18].fill(0xFF);
+        let mut ppu = ppu_with_chr(chr);
+        ppu.palette_table[0x11] = 0x30;
+        for sprite in 0..9 {
+            set_sprite(&mut ppu, sprite, sprite as u8 * 10, 19, 1, 0);
+        }
+
+        let mut frame = Frame::new();
+        ppu.render_sprites(&mut frame);
+
+        assert_eq!(frame.get_pixel(70, 20), SYSTEM_PALETTE[0x30]);
+        assert_eq!(frame.get_pixel(80, 20), (0, 0, 0));
+    }
+
+    #[test]
+    fn sprite_zero_over_opaque_background_sets_hit() {
+        let mut chr = vec![0; 0x2000];
+        chr[0x10..0x18].fill(0xFF); // tile 1: solid
+        let mut ppu = ppu_with_chr(chr);
+        ppu.vram[32 + 1] = 1; // opaque background at tile (1, 1)
+        set_sprite(&mut ppu, 0, 12, 7, 1, SPRITE_BEHIND_BACKGROUND);
+        ppu.write_register(0x2001, MASK_SHOW_BACKGROUND | MASK_SHOW_SPRITES);
+
+        // Sprite 0's first opaque pixel over the background is (12, 8)
+        ppu.tick(DOTS_PER_SCANLINE * 8 + 12);
+        assert_eq!(ppu.status & STATUS_SPRITE_ZERO_HIT, 0);
+        ppu.tick(1);
+        assert_eq!(
+            ppu.read_register(0x2002) & STATUS_SPRITE_ZERO_HIT,
+            STATUS_SPRITE_ZERO_HIT
+        );
+
+        ppu.pre_render();
+        assert_eq!(ppu.read_register(0x2002) & STATUS_SPRITE_ZERO_HIT, 0);
+    }
+
+    #[test]
+    fn transparent_pixels_do_not_set_sprite_zero_hit() {
+        let mut chr = vec![0; 0x2000];
+        chr[0x10..0x18].fill(0xFF); // tile 1: solid
+        let mut ppu = ppu_with_chr(chr);
+        ppu.vram[32 + 1] = 1; // opaque background at tile (1, 1)
+        set_sprite(&mut ppu, 0, 8, 7, 2, 0); // blank tile over the opaque background
+        set_sprite(&mut ppu, 1, 8, 7, 1, 0); // other sprites never count
+        set_sprite(&mut ppu, 2, 0, 7, 1, 0);
+        ppu.write_register(0x2001, MASK_SHOW_BACKGROUND | MASK_SHOW_SPRITES);
+
+        ppu.tick(DOTS_PER_SCANLINE * VISIBLE_SCANLINES as usize);
+        assert_eq!(ppu.read_register(0x2002) & STATUS_SPRITE_ZERO_HIT, 0);
+
+        // Solid sprite 0 over transparent background, on the next frame
+        set_sprite(&mut ppu, 0, 24, 7, 1, 0);
+        ppu.tick(DOTS_PER_SCANLINE * SCANLINES_PER_FRAME as usize);
+        assert_eq!(ppu.read_register(0x2002) & STATUS_SPRITE_ZERO_HIT, 0);
+    }
+
+    #[test]
+    fn sprite_zero_hit_needs_background_and_sprite_rendering() {
+        let mut chr = vec![0; 0x2000];
+        chr[0x10..0x18].fill(0xFF); // tile 1: solid
+        for mask in [0, MASK_SHOW_BACKGROUND, MASK_SHOW_SPRITES] {
+            let mut ppu = ppu_with_chr(chr.clone());
+            ppu.vram[32 + 1] = 1;
+            set_sprite(&mut ppu, 0, 12, 7, 1, 0);
+            ppu.write_register(0x2001, mask);
+
+            ppu.tick(DOTS_PER_SCANLINE * VISIBLE_SCANLINES as usize);
+            assert_eq!(ppu.status & STATUS_SPRITE_ZERO_HIT, 0, "mask {:08b}", mask);
+        }
+    }
+
+    #[test]
+    fn step_frame_alone_raises_sprite_zero_hit() {
+        let mut chr = vec![0; 0x2000];
+        chr[0x10..0x18].fill(0xFF); // tile 1: solid
+        let program = [
+            0xA9, 0x20, 0x8D, 0x06, 0x20, // LDA #$20; STA $2006
+            0xA9, 0x21, 0x8D, 0x06, 0x20, // LDA #$21; STA $2006
+            0xA9, 0x01, 0x8D, 0x07, 0x20, // LDA #$01; STA $2007   tile (1, 1)
+            0xA9, 0x00, 0x8D, 0x03, 0x20, // LDA #$00; STA $2003
+            0xA9, 0x07, 0x8D, 0x04, 0x20, // LDA #$07; STA $2004   sprite 0: Y
+            0xA9, 0x01, 0x8D, 0x04, 0x20, // LDA #$01; STA $2004   tile
+            0xA9, 0x00, 0x8D, 0x04, 0x20, // LDA #$00; STA $2004   attributes
+            0xA9, 0x0C, 0x8D, 0x04, 0x20, // LDA #$0C; STA $2004   X
+            0xA9, 0x00, 0x8D, 0x05, 0x20, // LDA #$00; STA $2005
+            0x8D, 0x05, 0x20, //             STA $2005
+            0xA9, 0x18, 0x8D, 0x01, 0x20, // LDA #$18; STA $2001   show background and sprites
+            0x2C, 0x02, 0x20, //             BIT $2002
+            0x50, 0xFB, //                   BVC -5
+            0xE6, 0x10, //                   INC $10
+            0x4C, 0x3C, 0x80, //             JMP $803C
+        ];
+        let mut prg = vec![0; 0x4000];
+        prg[..program.len()].copy_from_slice(&program);
+        prg[0x3FFC] = 0x00;
+        prg[0x3FFD] = 0x80;
+
+        let mapper: Box<dyn Mapper> = Box::new(Nrom::new(test_rom(prg, chr)));
+        let mut cpu = CPU::new();
+        cpu.bus = Bus::with_mapper(mapper);
+        cpu.reset();
+
+        cpu.step_frame().unwrap();
+        assert_eq!(cpu.mem_peek(0x10), 1);
+    }
+
+    #[test]
+    fn vertical_mirroring_aliases_0x2000_and_0x2800() {
+        let mut ppu = ppu_with_mirroring(Mirroring::Vertical);
+        write_vram(&mut ppu, 0x2005, 0x11);
+        write_vram(&mut ppu, 0x2405, 0x22);
+
+        assert_eq!(read_vram(&mut ppu, 0x2805), 0x11);
+        assert_eq!(read_vram(&mut ppu, 0x2C05), 0x22);
+    }
+
+    #[test]
+    fn horizontal_mirroring_aliases_0x2000_and_0x2400() {
+        let mut ppu = ppu_with_mirroring(Mirroring::Horizontal);
+        write_vram(&mut ppu, 0x2005, 0x11);
+        write_vram(&mut ppu, 0x2805, 0x22);
+
+        assert_eq!(read_vram(&mut ppu, 0x2405), 0x11);
+        assert_eq!(read_vram(&mut ppu, 0x2C05), 0x22);
+        assert_eq!(read_vram(&mut ppu, 0x3405), 0x11); // 0x3000-0x3EFF mirrors 0x2000
+    }
+
+    #[test]
+    fn single_screen_mirroring_aliases_every_nametable() {
+        for mirroring in [Mirroring::SingleScreenLower, Mirroring::SingleScreenUpper] {
+            let mut ppu = ppu_with_mirroring(mirroring);
+            write_vram(&mut ppu, 0x2C05, 0x33);
+
+            for nametable in [0x2005, 0x2405, 0x2805] {
+                assert_eq!(read_vram(&mut ppu, nametable), 0x33);
+            }
+        }
+
+        let mut lower = ppu_with_mirroring(Mirroring::SingleScreenLower);
+        let mut upper = ppu_with_mirroring(Mirroring::SingleScreenUpper);
+        write_vram(&mut lower, 0x2000, 0x44);
+        write_vram(&mut upper, 0x2000, 0x44);
+        assert_eq!(lower.vram[0x000], 0x44);
+        assert_eq!(upper.vram[0x400], 0x44);
+    }
+
+    #[test]
+    fn four_screen_mirroring_keeps_nametables_apart() {
+        let mut ppu = ppu_with_mirroring(Mirroring::FourScreen);
+        for (i, nametable) in [0x2005, 0x2405, 0x2805, 0x2C05].into_iter().enumerate() {
+            write_vram(&mut ppu, nametable, i as u8);
+        }
+
+        assert_eq!(read_vram(&mut ppu, 0x2005), 0);
+        assert_eq!(read_vram(&mut ppu, 0x2405), 1);
+        assert_eq!(read_vram(&mut ppu, 0x2805), 2);
+        assert_eq!(read_vram(&mut ppu, 0x2C05), 3);
+    }
+
+    #[test]
+    fn dots_until_vblank_and_frame_end() {
+        let mut ppu = Ppu::default();
+        assert_eq!(ppu.dots_until_vblank(), DOTS_PER_SCANLINE * 241);
+        assert_eq!(ppu.dots_until_frame_end(), DOTS_PER_SCANLINE * 262);
+
+        ppu.tick(DOTS_PER_SCANLINE * 241 + 5);
+        assert_eq!(ppu.dots_until_vblank(), DOTS_PER_SCANLINE * 262 - 5);
+        assert_eq!(ppu.dots_until_frame_end(), DOTS_PER_SCANLINE * 21 - 5);
+    }
+
+    #[test]
+    fn tick_enters_vblank_and_raises_nmi() {
+        let mut ppu = Ppu::default();
+        ppu.write_register(0x2000, CTRL_GENERATE_NMI);
+
+        ppu.tick(DOTS_PER_SCANLINE * 241 - 1);
+        assert!(!ppu.poll_nmi());
+        assert_eq!(ppu.peek_register(0x2002) & STATUS_VBLANK, 0);
+
+        ppu.tick(1);
+        assert_eq!(ppu.scanline(), 241);
+        assert!(ppu.poll_nmi());
+        assert!(!ppu.poll_nmi());
+        assert_eq!(ppu.peek_register(0x2002) & STATUS_VBLANK, STATUS_VBLANK);
+
+        // Cleared on the pre-render line, then the frame wraps
+        ppu.tick(DOTS_PER_SCANLINE * 20);
+        assert_eq!(ppu.peek_register(0x2002) & STATUS_VBLANK, 0);
+        ppu.tick(DOTS_PER_SCANLINE);
+        assert_eq!(ppu.scanline(), 0);
+        assert_eq!(ppu.frame_count(), 1);
+    }
+
+    #[test]
+    fn vblank_without_nmi_enabled_does_not_interrupt() {
+        let mut ppu = Ppu::default();
+        ppu.tick(DOTS_PER_SCANLINE * 241);
+        assert!(!ppu.poll_nmi());
+
+        // Turning NMI on mid-vblank fires immediately
+        ppu.write_register(0x2000, CTRL_GENERATE_NMI);
+        assert!(ppu.poll_nmi());
+    }
+
+    #[test]
+    fn render_fills_frame_with_backdrop_for_blank_background() {
+        let mut ppu = ppu_with_chr(vec![0; 0x2000]);
+        ppu.palette_table[0] = 0x21;
+
+        let mut frame = Frame::new();
+        ppu.render(&mut frame);
+
+        for y in 0..Frame::HEIGHT {
+            for x in 0..Frame::WIDTH {
+                assert_eq!(frame.get_pixel(x, y), SYSTEM_PALETTE[0x21]);
+            }
+        }
+    }
+}
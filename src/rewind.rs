@@ -0,0 +1,135 @@
+#![allow(dead_code)]
+
+use crate::cpu::CPU;
+use std::collections::VecDeque;
+
+// Keeps the last `capacity` save states, one every `interval` frames.
+// Call record after each frame; rewind steps back to the newest stored state.
+pub struct RewindBuffer {
+    states: VecDeque<Vec<u8>>,
+    capacity: usize,
+    interval: u64,
+    frames: u64,
+}
+
+impl RewindBuffer {
+    pub fn new(interval: u64, capacity: usize) -> Self {
+        RewindBuffer {
+            states: VecDeque::with_capacity(capacity),
+            capacity,
+            interval: interval.max(1),
+            frames: 0,
+        }
+    }
+
+    // Counts a finished frame and snapshots the machine if the interval is up.
+    // The oldest state is dropped once the buffer is full.
+    pub fn record(&mut self, cpu: &CPU) {
+        self.frames += 1;
+        if !self.frames.is_multiple_of(self.interval) || self.capacity == 0 {
+            return;
+        }
+
+        if self.states.len() == self.capacity {
+            self.states.pop_front();
+        }
+        self.states.push_back(cpu.save_state());
+    }
+
+    // Restores and discards the most recent state. Returns Ok(false) when nothing is left.
+    // A state that doesn't fit `cpu` (e.g. another cartridge) is kept and the error returned.
+    pub fn rewind(&mut self, cpu: &mut CPU) -> Result<bool, String> {
+        let Some(state) = self.states.pop_back() else {
+            return Ok(false);
+        };
+        if let Err(e) = cpu.load_state(&state) {
+            self.states.push_back(state);
+            return Err(e);
+        }
+        Ok(true)
+    }
+
+    pub fn len(&self) -> usize {
+        self.states.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.states.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::cpu::Mem;
+    use crate::mapper::test::test_rom;
+
+    // Counts frames in X from the vblank NMI: LDA #$80; STA $2000; JMP $8005
+    fn counting_cpu() -> CPU {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0xA9, 0x80, 0x8D, 0x00, 0x20, 0x4C, 0x05, 0x80]);
+        cpu.reset();
+        cpu.mem_write_u16(0xFFFA, 0x9000);
+        cpu.mem_write(0x9000, 0xE8); // INX
+        cpu.mem_write(0x9001, 0x40); // RTI
+        cpu
+    }
+
+    #[test]
+    fn rewinds_to_earlier_snapshots() {
+        let mut cpu = counting_cpu();
+        let mut rewind = RewindBuffer::new(2, 8);
+        let mut expected = Vec::new();
+
+        for _ in 0..6 {
+            cpu.step_frame().unwrap();
+            rewind.record(&cpu);
+            if cpu.bus.ppu.frame_count().is_multiple_of(2) {
+                expected.push(cpu.save_state());
+            }
+        }
+        assert_eq!(rewind.len(), 3);
+
+        assert_eq!(rewind.rewind(&mut cpu), Ok(true));
+        assert_eq!(rewind.rewind(&mut cpu), Ok(true));
+        assert_eq!(cpu.save_state(), expected[1]);
+        assert_eq!(cpu.bus.ppu.frame_count(), 4);
+        assert_eq!(cpu.register_x, 4);
+    }
+
+    #[test]
+    fn drops_oldest_states_when_full() {
+        let mut cpu = counting_cpu();
+        let mut rewind = RewindBuffer::new(1, 2);
+
+        for _ in 0..5 {
+            cpu.step_frame().unwrap();
+            rewind.record(&cpu);
+        }
+        assert_eq!(rewind.len(), 2);
+
+        assert_eq!(rewind.rewind(&mut cpu), Ok(true));
+        assert_eq!(rewind.rewind(&mut cpu), Ok(true));
+        assert_eq!(cpu.bus.ppu.frame_count(), 4);
+        assert_eq!(rewind.rewind(&mut cpu), Ok(false));
+        assert!(rewind.is_empty());
+    }
+
+    #[test]
+    fn rewinding_into_another_machine_is_an_error() {
+        let mut cpu = counting_cpu();
+        let mut rewind = RewindBuffer::new(1, 2);
+        cpu.step_frame().unwrap();
+        rewind.record(&cpu);
+
+        // CHR RAM on the cartridge adds state the snapshot doesn't have
+        let rom = test_rom(vec![0; 0x4000], vec![]);
+        let mut other = CPU::new();
+        other.bus = Bus::with_rom(rom).unwrap();
+
+        assert!(rewind.rewind(&mut other).is_err());
+        assert_eq!(rewind.len(), 1);
+        assert_eq!(rewind.rewind(&mut cpu), Ok(true));
+    }
+}
@@ -0,0 +1,146 @@
+// Helpers for save states: a flat little-endian byte stream that each component
+// writes its fields into and reads back in the same order.
+
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl Default for StateWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        StateWriter { data: Vec::new() }
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn usize(&mut self, value: usize) {
+        self.u64(value as u64);
+    }
+
+    // Length-prefixed so a buffer of the wrong size is caught on load
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.u64(bytes.len() as u64);
+        self.data.extend_from_slice(bytes);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        StateReader { data, pos: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self.pos + len;
+        if end > self.data.len() {
+            return Err("Save state is truncated".to_string());
+        }
+        let slice = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, String> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u16(&mut self) -> Result<u16, String> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, String> {
+        let bytes = self.take(8)?;
+        Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    pub fn usize(&mut self) -> Result<usize, String> {
+        Ok(self.u64()? as usize)
+    }
+
+    // Fills `out` exactly; the saved buffer must have the same length
+    pub fn bytes(&mut self, out: &mut [u8]) -> Result<(), String> {
+        let len = self.usize()?;
+        if len != out.len() {
+            return Err(format!(
+                "Save state buffer is {} bytes, expected {}",
+                len,
+                out.len()
+            ));
+        }
+        out.copy_from_slice(self.take(len)?);
+        Ok(())
+    }
+
+    pub fn is_at_end(&self) -> bool {
+        self.pos == self.data.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn values_round_trip() {
+        let mut writer = StateWriter::new();
+        writer.u8(0x12);
+        writer.bool(true);
+        writer.u16(0xBEEF);
+        writer.usize(123_456);
+        writer.bytes(&[1, 2, 3]);
+        let data = writer.into_bytes();
+
+        let mut reader = StateReader::new(&data);
+        assert_eq!(reader.u8(), Ok(0x12));
+        assert_eq!(reader.bool(), Ok(true));
+        assert_eq!(reader.u16(), Ok(0xBEEF));
+        assert_eq!(reader.usize(), Ok(123_456));
+        let mut buf = [0; 3];
+        assert_eq!(reader.bytes(&mut buf), Ok(()));
+        assert_eq!(buf, [1, 2, 3]);
+        assert!(reader.is_at_end());
+        assert!(reader.u8().is_err());
+    }
+
+    #[test]
+    fn mismatched_buffer_size_is_an_error() {
+        let mut writer = StateWriter::new();
+        writer.bytes(&[0; 4]);
+        let data = writer.into_bytes();
+
+        let mut buf = [0; 8];
+        assert!(StateReader::new(&data).bytes(&mut buf).is_err());
+    }
+}
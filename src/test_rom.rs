@@ -0,0 +1,116 @@
+#![allow(dead_code)]
+
+// Runner for blargg-style test ROMs. They report through cartridge RAM:
+// 0x6000 holds the status (0x80 while running, 0x81 to ask for a reset,
+// anything else is the final result code), 0x6001-0x6003 hold the signature
+// DE B0 61 once the protocol is live, and 0x6004 starts a NUL-terminated message.
+
+use crate::bus::Bus;
+use crate::cartridge::Rom;
+use crate::cpu::{CPU, Mem};
+
+const STATUS: u16 = 0x6000;
+const SIGNATURE: u16 = 0x6001;
+const SIGNATURE_BYTES: [u8; 3] = [0xDE, 0xB0, 0x61];
+const MESSAGE: u16 = 0x6004;
+const MESSAGE_MAX_LEN: u16 = 0x1000;
+
+const STATUS_RUNNING: u8 = 0x80;
+const STATUS_RESET_REQUESTED: u8 = 0x81;
+const STATUS_PASSED: u8 = 0x00;
+
+// Generous bound: the slowest suites finish in well under a minute of NES time
+const MAX_FRAMES: usize = 60 * 60;
+
+fn protocol_live(cpu: &CPU) -> bool {
+    (0..3).all(|i| cpu.mem_peek(SIGNATURE + i) == SIGNATURE_BYTES[i as usize])
+}
+
+fn read_message(cpu: &CPU) -> String {
+    let bytes: Vec<u8> = (0..MESSAGE_MAX_LEN)
+        .map(|i| cpu.mem_peek(MESSAGE + i))
+        .take_while(|&b| b != 0)
+        .collect();
+    String::from_utf8_lossy(&bytes).trim().to_string()
+}
+
+// Runs the ROM until it reports a result. Ok on a pass, otherwise Err with the
+// result code and the ROM's own message.
+pub fn run_test_rom(rom: Rom) -> Result<(), String> {
+    let mut cpu = CPU::new();
+    cpu.bus = Bus::with_rom(rom)?;
+    cpu.reset();
+
+    let mut started = false;
+    for _ in 0..MAX_FRAMES {
+        if cpu.step_frame().map_err(|e| e.to_string())? {
+            return Err("CPU halted before the test finished".to_string());
+        }
+        if !protocol_live(&cpu) {
+            continue;
+        }
+
+        match cpu.mem_peek(STATUS) {
+            STATUS_RUNNING => started = true,
+            STATUS_RESET_REQUESTED => cpu.reset(),
+            STATUS_PASSED if started => return Ok(()),
+            code if started => {
+                return Err(format!(
+                    "Test failed with code {}: {}",
+                    code,
+                    read_message(&cpu)
+                ));
+            }
+            _ => {}
+        }
+    }
+    Err(format!("Test did not finish within {} frames", MAX_FRAMES))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::Mirroring;
+    use crate::mapper::test::test_rom;
+
+    // Assembles a fake test ROM that marks itself running, then writes `message`
+    // and finishes with `result`
+    fn fake_test_rom(result: u8, message: &str) -> Rom {
+        let mut program = vec![
+            0xA9, 0xDE, 0x8D, 0x01, 0x60, // LDA #$DE; STA $6001
+            0xA9, 0xB0, 0x8D, 0x02, 0x60, // LDA #$B0; STA $6002
+            0xA9, 0x61, 0x8D, 0x03, 0x60, // LDA #$61; STA $6003
+            0xA9, 0x80, 0x8D, 0x00, 0x60, // LDA #$80; STA $6000
+        ];
+        // Burn a couple of frames while "running"
+        program.extend([0xA2, 0x00, 0xA0, 0x00, 0xC8, 0xD0, 0xFD, 0xE8, 0xD0, 0xF8]);
+        for (i, byte) in message.bytes().chain([0]).enumerate() {
+            let addr = MESSAGE + i as u16;
+            program.extend([0xA9, byte, 0x8D, addr as u8, (addr >> 8) as u8]);
+        }
+        program.extend([0xA9, result, 0x8D, 0x00, 0x60]); // LDA #result; STA $6000
+        let spin = 0x8000 + program.len() as u16;
+        program.extend([0x4C, spin as u8, (spin >> 8) as u8]); // JMP spin
+
+        let mut prg = vec![0; 0x4000];
+        prg[..program.len()].copy_from_slice(&program);
+        prg[0x3FFC] = 0x00;
+        prg[0x3FFD] = 0x80;
+        let mut rom = test_rom(prg, vec![0; 0x2000]);
+        rom.screen_mirroring = Mirroring::Vertical;
+        rom
+    }
+
+    #[test]
+    fn passing_rom_returns_ok() {
+        assert_eq!(run_test_rom(fake_test_rom(0x00, "Passed")), Ok(()));
+    }
+
+    #[test]
+    fn failing_rom_reports_code_and_message() {
+        assert_eq!(
+            run_test_rom(fake_test_rom(0x03, "BCC timing\n")),
+            Err("Test failed with code 3: BCC timing".to_string())
+        );
+    }
+}
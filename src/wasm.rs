@@ -0,0 +1,125 @@
+#![allow(dead_code)]
+
+// A small, flat API over the emulator for browser front ends: load a ROM,
+// run a frame, hand over the pixels, feed in buttons. Everything here is
+// plain data so it can be exported through wasm-bindgen. Built with the
+// `wasm` feature only.
+
+use crate::bus::Bus;
+use crate::cartridge::Rom;
+use crate::cpu::CPU;
+use crate::frame::Frame;
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+pub struct WasmNes {
+    cpu: CPU,
+    frame: Frame,
+}
+
+#[wasm_bindgen]
+impl WasmNes {
+    // Parses an iNES image and resets the console
+    #[wasm_bindgen(constructor)]
+    pub fn new(rom_bytes: &[u8]) -> Result<WasmNes, String> {
+        let rom = Rom::from_bytes(rom_bytes)?;
+        let mut cpu = CPU::new();
+        cpu.bus = Bus::with_rom(rom)?;
+        cpu.reset();
+
+        Ok(WasmNes {
+            cpu,
+            frame: Frame::new(),
+        })
+    }
+
+    // Emulates until the PPU finishes a frame, then redraws the frame buffer
+    pub fn step_frame(&mut self) -> Result<(), String> {
+        self.cpu.step_frame().map_err(|e| e.to_string())?;
+        self.cpu.bus.ppu.render(&mut self.frame);
+        Ok(())
+    }
+
+    // RGB24, 256x240. Valid until the next step_frame.
+    pub fn frame_buffer(&self) -> *const u8 {
+        self.frame.as_bytes().as_ptr()
+    }
+
+    pub fn frame_buffer_len(&self) -> usize {
+        self.frame.as_bytes().len()
+    }
+
+    // `index` is the controller bit: 0 A, 1 B, 2 Select, 3 Start, 4-7 Up/Down/Left/Right
+    pub fn set_button(&mut self, index: u8, pressed: bool) {
+        if index < 8 {
+            self.cpu.bus.joypad1.set_button_pressed(1 << index, pressed);
+        }
+    }
+}
+
+// Native-side access; references cannot cross the wasm boundary
+impl WasmNes {
+    pub fn frame(&self) -> &Frame {
+        &self.frame
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::{TestRom, create_rom};
+    use crate::cpu::Mem;
+    use crate::palette::SYSTEM_PALETTE;
+
+    // One 16KB NROM bank that sets the backdrop colour to $21 and spins
+    fn backdrop_rom() -> Vec<u8> {
+        let mut prg = vec![0; 0x4000];
+        let program = [
+            0xA9, 0x3F, 0x8D, 0x06, 0x20, // LDA #$3F; STA $2006
+            0xA9, 0x00, 0x8D, 0x06, 0x20, // LDA #$00; STA $2006
+            0xA9, 0x21, 0x8D, 0x07, 0x20, // LDA #$21; STA $2007
+            0x4C, 0x0F, 0x80, //             JMP $800F
+        ];
+        prg[..program.len()].copy_from_slice(&program);
+        prg[0x3FFC] = 0x00;
+        prg[0x3FFD] = 0x80;
+
+        create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x00, 0x00, 0, 0, 0, 0, 0, 0, 0, 0,
+            ],
+            trainer: None,
+            prg_rom: prg,
+            chr_rom: vec![0; 0x2000],
+        })
+    }
+
+    #[test]
+    fn runs_a_frame_and_exposes_pixels() {
+        let mut nes = WasmNes::new(&backdrop_rom()).unwrap();
+        nes.step_frame().unwrap();
+
+        assert_eq!(nes.frame_buffer(), nes.frame().as_bytes().as_ptr());
+        assert_eq!(nes.frame_buffer_len(), Frame::WIDTH * Frame::HEIGHT * 3);
+        assert_eq!(nes.frame().get_pixel(0, 0), SYSTEM_PALETTE[0x21]);
+        assert_eq!(nes.frame().get_pixel(255, 239), SYSTEM_PALETTE[0x21]);
+    }
+
+    #[test]
+    fn set_button_reaches_the_controller() {
+        let mut nes = WasmNes::new(&backdrop_rom()).unwrap();
+        nes.set_button(3, true); // Start
+
+        nes.cpu.mem_write(0x4016, 1);
+        nes.cpu.mem_write(0x4016, 0);
+        let reads: Vec<u8> = (0..4).map(|_| nes.cpu.mem_read(0x4016) & 1).collect();
+        assert_eq!(reads, vec![0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn rejects_unsupported_mappers() {
+        let mut rom = backdrop_rom();
+        rom[6] = 0x40; // mapper 4
+        assert!(WasmNes::new(&rom).is_err());
+    }
+}